@@ -1,3 +1,5 @@
+#![allow(clippy::iter_skip_next, clippy::write_with_newline)]
+
 use std::{env, io::Write};
 
 use tsnet::{Network, ServerBuilder};
@@ -11,6 +13,7 @@ fn main() {
         .hostname("libtailscale-rs-echoclient")
         .ephemeral()
         .authkey(env::var("TS_AUTHKEY").expect("set TS_AUTHKEY in environment"))
+        .wait_for_up()
         .build()
         .unwrap();
 
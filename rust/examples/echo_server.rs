@@ -15,11 +15,20 @@ fn main() {
         .unwrap();
     let ln = ts.listen(Network::Tcp, ":1999").unwrap();
 
-    for conn in ln {
-        match conn {
+    loop {
+        match ln.accept_incoming() {
             Ok(conn) => {
+                match conn.identity() {
+                    Ok(who) => log::info!(
+                        "connection from {} ({})",
+                        conn.remote_addr,
+                        who.user_profile.login_name
+                    ),
+                    Err(err) => log::info!("connection from {} ({err})", conn.remote_addr),
+                }
+
                 thread::spawn(move || {
-                    handle_client(conn);
+                    handle_client(conn.stream);
                 });
             }
             Err(err) => panic!("{err}"),
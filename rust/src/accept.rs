@@ -0,0 +1,100 @@
+//! Receiving connections from the Go side of a listener.
+//!
+//! A listener handle is one half of a socketpair, over which each accepted connection is
+//! passed as a file descriptor, along with a flags byte and the remote address.
+//! This is what `tailscale_accept_info` does in C, done here so that it can be driven by
+//! any event loop once the handle is non-blocking.
+
+use std::{
+    io::{self, IoSliceMut},
+    net::SocketAddr,
+    os::fd::{AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+};
+
+use nix::sys::socket::{recvmsg, ControlMessageOwned, MsgFlags};
+
+use crate::sys;
+
+/// Received fds are close-on-exec, so that they don't leak into child processes spawned
+/// before they are wrapped. That is atomic where `MSG_CMSG_CLOEXEC` is supported, and done
+/// right after receiving them elsewhere.
+#[cfg(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd",
+    target_os = "openbsd"
+))]
+const RECV_FLAGS: MsgFlags = MsgFlags::MSG_CMSG_CLOEXEC;
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "netbsd",
+    target_os = "openbsd"
+)))]
+const RECV_FLAGS: MsgFlags = MsgFlags::empty();
+
+/// A connection received from a listener, not yet wrapped in a stream type.
+pub(crate) struct Accepted {
+    pub(crate) fd: OwnedFd,
+    pub(crate) remote_addr: SocketAddr,
+    pub(crate) via_funnel: bool,
+}
+
+/// Receive the next connection from the listener handle `fd`.
+///
+/// Returns `Ok(None)` once the Go side hung up, as the listener or its server was closed.
+/// If `fd` is non-blocking and no connection is pending, this fails with
+/// [`io::ErrorKind::WouldBlock`].
+pub(crate) fn recv_conn(fd: BorrowedFd<'_>) -> io::Result<Option<Accepted>> {
+    let mut buf = [0; 256];
+    let mut cmsg = nix::cmsg_space!([RawFd; 1]);
+
+    let (n, conn) = {
+        let mut iov = [IoSliceMut::new(&mut buf)];
+        let msg = recvmsg::<()>(fd.as_raw_fd(), &mut iov, Some(&mut cmsg), RECV_FLAGS)?;
+
+        let mut conn = None;
+        for cmsg in msg.cmsgs() {
+            if let ControlMessageOwned::ScmRights(fds) = cmsg {
+                for fd in fds {
+                    // Take ownership of every fd received, so that extra ones get closed.
+                    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+                    if RECV_FLAGS.is_empty() {
+                        unsafe {
+                            nix::libc::fcntl(
+                                fd.as_raw_fd(),
+                                nix::libc::F_SETFD,
+                                nix::libc::FD_CLOEXEC,
+                            )
+                        };
+                    }
+                    conn.get_or_insert(fd);
+                }
+            }
+        }
+        (msg.bytes, conn)
+    };
+
+    let conn = match conn {
+        Some(conn) if n > 0 => conn,
+        _ => return Ok(None),
+    };
+
+    let addr = String::from_utf8_lossy(&buf[1..n]);
+    let remote_addr = addr.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid remote address: {addr}"),
+        )
+    })?;
+
+    Ok(Some(Accepted {
+        fd: conn,
+        remote_addr,
+        via_funnel: buf[0] as u32 & sys::TAILSCALE_CONN_FUNNEL != 0,
+    }))
+}
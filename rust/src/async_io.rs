@@ -0,0 +1,230 @@
+//! Async tailnet sockets for any runtime, with [`async-io`](async_io).
+//!
+//! Requires the `async-io` feature.
+//!
+//! This is what the `tokio` feature provides, for runtimes built on `async-io` like smol and
+//! async-std: listeners, connections and packet sockets are driven by the `async-io` reactor,
+//! without blocking any threads. Connections are [`Conn`]s of [`Async<TcpStream>`], which
+//! implement the `futures` I/O traits.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use tsnet::{async_io, Network, ServerBuilder};
+//!
+//! # async fn run() -> tsnet::Result<()> {
+//! let ts = ServerBuilder::new().ephemeral().build()?;
+//! async_io::up(&ts).await?;
+//!
+//! let ln = async_io::listen(&ts, Network::Tcp, ":1999")?;
+//! loop {
+//!     let conn = ln.accept_incoming().await?;
+//!     println!("connection from {}", conn.remote_addr);
+//! }
+//! # }
+//! ```
+
+use std::{
+    ffi::{c_int, CString},
+    future::poll_fn,
+    io,
+    net::{SocketAddr, TcpStream},
+    os::fd::{AsFd, OwnedFd},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use async_io::Async;
+use futures_core::Stream;
+use futures_io::{AsyncRead, AsyncWrite};
+
+use crate::{
+    accept, op_result, sys, Conn, Error, IncomingConn, Inner, Network, Result, Server, UpState,
+};
+
+/// Like [`Server::up`], but waits asynchronously on the `async-io` reactor.
+///
+/// Dropping the future cancels waiting.
+pub async fn up(ts: &Server) -> Result<UpState> {
+    let handle = ts.inner.handle()?;
+    op(ts, |op, error| unsafe {
+        sys::tailscale_up_start(handle, op, error)
+    })
+    .await?;
+    ts.up_state()
+}
+
+/// Like [`Server::connect`], but connects asynchronously on the `async-io` reactor.
+///
+/// Dropping the future cancels connecting.
+pub async fn connect(ts: &Server, network: Network, addr: &str) -> Result<Conn<Async<TcpStream>>> {
+    let handle = ts.inner.handle()?;
+    let network = CString::new(format!("{}", network)).unwrap();
+    let addr = CString::new(addr)?;
+
+    let conn = op(ts, |op, error| unsafe {
+        sys::tailscale_dial_start(handle, network.as_ptr(), addr.as_ptr(), op, error)
+    })
+    .await?
+    .ok_or_else(|| Error::TSNet("dial returned no connection".to_string()))?;
+
+    Ok(Conn::new(&ts.inner, Async::new(TcpStream::from(conn))?))
+}
+
+/// Like [`Server::listen`], but for accepting asynchronously on the `async-io` reactor.
+pub fn listen(ts: &Server, network: Network, address: &str) -> Result<Listener> {
+    Listener::new(ts.listen(network, address)?)
+}
+
+/// Like [`Server::listen_packet`], but for a socket driven by the `async-io` reactor.
+pub fn listen_packet(ts: &Server, addr: &str) -> Result<PacketSocket> {
+    Ok(PacketSocket::new(ts.listen_packet(addr)?)?)
+}
+
+/// Run an operation in the background with `start`, and wait for it to be done.
+///
+/// See `Server::op`, which does the same on tokio.
+async fn op(
+    ts: &Server,
+    start: impl FnOnce(*mut sys::tailscale_op, *mut sys::tailscale_error) -> c_int,
+) -> Result<Option<OwnedFd>> {
+    let op = Async::new(ts.start_op(start)?)?;
+    op.read_with(|fd| op_result(fd.as_fd())).await?
+}
+
+/// A [`Listener`](crate::Listener) driven by the `async-io` reactor, as created by
+/// [`listen`].
+///
+/// The listener is also a [`Stream`] of connections, ending once the server has been closed.
+#[derive(Debug)]
+pub struct Listener {
+    ts: Arc<Inner>,
+    handle: Async<OwnedFd>,
+}
+
+impl Listener {
+    /// Make a listener async, registering it with the `async-io` reactor.
+    pub fn new(listener: crate::Listener) -> Result<Self> {
+        let crate::Listener { ts, handle } = listener;
+        Ok(Listener {
+            ts,
+            handle: Async::new(handle)?,
+        })
+    }
+
+    /// Accept a new incoming connection from this listener.
+    ///
+    /// Returns [`Error::ServerClosed`] once the server of this listener has been closed.
+    pub async fn accept(&self) -> Result<Conn<Async<TcpStream>>> {
+        self.accept_incoming().await.map(IncomingConn::into_conn)
+    }
+
+    /// Accept a new incoming connection from this listener, along with its remote address.
+    ///
+    /// Returns [`Error::ServerClosed`] once the server of this listener has been closed.
+    pub async fn accept_incoming(&self) -> Result<IncomingConn<Async<TcpStream>>> {
+        poll_fn(|cx| self.poll_accept(cx)).await
+    }
+
+    /// Poll for a new incoming connection, registering the current task for wakeup once one
+    /// is pending.
+    pub fn poll_accept(
+        &self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<IncomingConn<Async<TcpStream>>>> {
+        loop {
+            match self.handle.poll_readable(cx) {
+                Poll::Ready(ready) => ready?,
+                Poll::Pending => return Poll::Pending,
+            }
+            let conn = match accept::recv_conn(self.handle.get_ref().as_fd()) {
+                Ok(conn) => conn.ok_or(Error::ServerClosed)?,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(err) => return Poll::Ready(Err(err.into())),
+            };
+
+            let conn = IncomingConn::new(&self.ts, conn, |fd| Async::new(TcpStream::from(fd)))?;
+            return Poll::Ready(Ok(conn));
+        }
+    }
+}
+
+/// Streams accepted connections, ending once the server has been closed.
+impl Stream for Listener {
+    type Item = io::Result<Conn<Async<TcpStream>>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.poll_accept(cx) {
+            Poll::Ready(Ok(conn)) => Poll::Ready(Some(Ok(conn.into_conn()))),
+            Poll::Ready(Err(Error::ServerClosed)) => Poll::Ready(None),
+            Poll::Ready(Err(err)) => Poll::Ready(Some(Err(err.into()))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Reads from the stream of the connection.
+impl<S: AsyncRead + Unpin> AsyncRead for Conn<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+/// Writes to the stream of the connection.
+impl<S: AsyncWrite + Unpin> AsyncWrite for Conn<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_close(cx)
+    }
+}
+
+/// A [`PacketSocket`](crate::PacketSocket) driven by the `async-io` reactor, as created by
+/// [`listen_packet`].
+#[derive(Debug)]
+pub struct PacketSocket {
+    socket: Async<crate::PacketSocket>,
+}
+
+impl PacketSocket {
+    /// Make a packet socket async, registering it with the `async-io` reactor.
+    pub fn new(socket: crate::PacketSocket) -> io::Result<Self> {
+        Ok(PacketSocket {
+            socket: Async::new(socket)?,
+        })
+    }
+
+    /// Receive a single datagram, returning the number of bytes read and where it came from.
+    ///
+    /// See [`crate::PacketSocket::recv_from`].
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.socket.read_with(|socket| socket.recv_from(buf)).await
+    }
+
+    /// Send a single datagram to the given peer, returning the number of bytes sent.
+    pub async fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        self.socket
+            .write_with(|socket| socket.send_to(buf, addr))
+            .await
+    }
+
+    /// Get a reference to the underlying packet socket.
+    pub fn get_ref(&self) -> &crate::PacketSocket {
+        self.socket.get_ref()
+    }
+}
@@ -0,0 +1,146 @@
+//! Serving [`axum`] apps on the tailnet.
+//!
+//! Requires the `axum` feature.
+//!
+//! [`AsyncListener`] implements axum's [`Listener`], so it can be passed to [`axum::serve`].
+//! Serving with [`into_make_service_with_connect_info::<PeerAddr>`] makes the peer of each
+//! request available to handlers, either as [`ConnectInfo<PeerAddr>`] or as the identity of
+//! the caller with the [`TailnetPeer`] extractor.
+//!
+//! [`axum::serve`]: ::axum::serve()
+//! [`into_make_service_with_connect_info::<PeerAddr>`]: ::axum::Router::into_make_service_with_connect_info
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use axum::{http::StatusCode, routing::get, Router};
+//! use tsnet::{axum::TailnetPeer, Network, PeerAddr, ServerBuilder};
+//!
+//! async fn hello(TailnetPeer(who): TailnetPeer) -> Result<String, StatusCode> {
+//!     if !who.node.tags.iter().any(|tag| tag == "tag:admin") {
+//!         return Err(StatusCode::FORBIDDEN);
+//!     }
+//!     Ok(format!("Hello, {}!", who.user_profile.login_name))
+//! }
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let ts = ServerBuilder::new().ephemeral().build()?;
+//! let ln = ts.listen_async(Network::Tcp, ":80")?;
+//! let app = Router::new().route("/", get(hello));
+//! axum::serve(ln, app.into_make_service_with_connect_info::<PeerAddr>()).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{io, time::Duration};
+
+use ::axum::{
+    extract::{connect_info::Connected, ConnectInfo, FromRequestParts},
+    http::{request::Parts, StatusCode},
+    response::{IntoResponse, Response},
+    serve::{IncomingStream, Listener},
+};
+use tokio::net::TcpStream;
+
+use crate::{local, AsyncListener, Conn, Error, PeerAddr};
+
+/// Accepts connections until the server of the listener is closed.
+///
+/// Errors are logged and accepting is retried, as axum expects. Once the server has been
+/// closed, `accept` never completes, so use [`with_graceful_shutdown`] to stop serving.
+///
+/// There is no local address, so `local_addr` always fails.
+///
+/// [`with_graceful_shutdown`]: ::axum::serve::Serve::with_graceful_shutdown
+impl Listener for AsyncListener {
+    type Io = Conn<TcpStream>;
+    type Addr = PeerAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        loop {
+            match self.accept_incoming().await {
+                Ok(conn) => {
+                    let peer = conn.peer();
+                    return (conn.into_conn(), peer);
+                }
+                Err(Error::ServerClosed) => {
+                    log::debug!("server closed, no longer accepting connections");
+                    std::future::pending::<()>().await;
+                }
+                Err(err) => {
+                    log::error!("error accepting connection: {err}");
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "tailnet listeners have no local address",
+        ))
+    }
+}
+
+impl Connected<IncomingStream<'_, AsyncListener>> for PeerAddr {
+    fn connect_info(stream: IncomingStream<'_, AsyncListener>) -> Self {
+        stream.remote_addr().clone()
+    }
+}
+
+/// Extracts the tailnet identity of the caller.
+///
+/// Requests from Funnel have no tailnet identity and are rejected with `403 Forbidden`.
+/// Requires serving with `into_make_service_with_connect_info::<PeerAddr>()`.
+#[derive(Clone, Debug)]
+pub struct TailnetPeer(pub local::WhoIs);
+
+impl<S: Send + Sync> FromRequestParts<S> for TailnetPeer {
+    type Rejection = TailnetPeerRejection;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        let Some(ConnectInfo(peer)) = parts.extensions.get::<ConnectInfo<PeerAddr>>() else {
+            return Err(TailnetPeerRejection::MissingConnectInfo);
+        };
+        if peer.via_funnel {
+            return Err(TailnetPeerRejection::ViaFunnel);
+        }
+
+        let peer = peer.clone();
+        let who = tokio::task::spawn_blocking(move || peer.identity())
+            .await
+            .map_err(|err| Error::IO(err.into()))
+            .and_then(|who| who)
+            .map_err(TailnetPeerRejection::Lookup)?;
+        Ok(TailnetPeer(who))
+    }
+}
+
+/// Why [`TailnetPeer`] could not be extracted.
+#[derive(Debug, thiserror::Error)]
+pub enum TailnetPeerRejection {
+    /// The app is not served with `into_make_service_with_connect_info::<PeerAddr>()`.
+    #[error(
+        "missing connection info, serve with into_make_service_with_connect_info::<PeerAddr>()"
+    )]
+    MissingConnectInfo,
+    /// The request came in via Funnel.
+    #[error("connection via funnel has no tailnet identity")]
+    ViaFunnel,
+    /// Looking up the identity failed.
+    #[error("looking up tailnet identity: {0}")]
+    Lookup(Error),
+}
+
+impl IntoResponse for TailnetPeerRejection {
+    fn into_response(self) -> Response {
+        let status = match self {
+            TailnetPeerRejection::ViaFunnel => StatusCode::FORBIDDEN,
+            TailnetPeerRejection::MissingConnectInfo | TailnetPeerRejection::Lookup(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        (status, self.to_string()).into_response()
+    }
+}
@@ -0,0 +1,73 @@
+//! Connecting HTTP clients to the tailnet.
+
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use hyper::Uri;
+use hyper_util::{
+    client::legacy::connect::{Connected, Connection},
+    rt::TokioIo,
+};
+use tokio::net::TcpStream;
+use tower_service::Service;
+
+use crate::{Conn, Error, Network, Result, Server};
+
+/// Connects to the host and port of a URI over the tailnet, through [`Server::connect_async`].
+///
+/// Hosts can be tailnet addresses or MagicDNS names, short or fully qualified. The port
+/// defaults to 443 for `https` URIs and to 80 otherwise. Only the connection is made here:
+/// for `https`, TLS is up to the client using the connector.
+///
+/// This is a connector for hyper-util's legacy `Client`, as `tsnet::http::Connector` with
+/// the `http` feature, and for tonic's `Endpoint::connect_with_connector`, as
+/// `tsnet::tonic::Connector` with the `tonic` feature.
+#[derive(Clone, Debug)]
+pub struct Connector {
+    ts: Server,
+}
+
+impl Connector {
+    /// Connect through the node `ts`.
+    pub fn new(ts: Server) -> Self {
+        Connector { ts }
+    }
+}
+
+impl Service<Uri> for Connector {
+    type Response = TokioIo<Conn<TcpStream>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let ts = self.ts.clone();
+        Box::pin(async move {
+            let host = uri.host().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("no host in {uri}"))
+            })?;
+            let port = match uri.port_u16() {
+                Some(port) => port,
+                None if uri.scheme_str() == Some("https") => 443,
+                None => 80,
+            };
+            let stream = ts
+                .connect_async(Network::Tcp, &format!("{host}:{port}"))
+                .await?;
+            Ok(TokioIo::new(stream))
+        })
+    }
+}
+
+impl<S: Connection> Connection for Conn<S> {
+    fn connected(&self) -> Connected {
+        self.stream.connected()
+    }
+}
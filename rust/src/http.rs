@@ -0,0 +1,161 @@
+//! HTTP servers and clients on the tailnet with [`hyper`].
+//!
+//! Requires the `http` feature.
+//!
+//! [`serve`] serves HTTP on a tailnet listener, and [`Connector`] connects hyper-util's
+//! `Client` to other tailnet nodes. With the `reqwest` feature, `reqwest_client` makes a
+//! reqwest client that does the same through the loopback server.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use std::convert::Infallible;
+//!
+//! use hyper::{body::Incoming, service::service_fn, Request, Response};
+//! use tsnet::{Network, ServerBuilder};
+//!
+//! async fn hello(_: Request<Incoming>) -> Result<Response<String>, Infallible> {
+//!     Ok(Response::new("Hello, tailnet!".to_string()))
+//! }
+//!
+//! # async fn run() -> tsnet::Result<()> {
+//! let ts = ServerBuilder::new().ephemeral().build()?;
+//! let ln = ts.listen_async(Network::Tcp, ":80")?;
+//! tsnet::http::serve(ln, service_fn(hello)).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Fetching from another node, reusing connections:
+//!
+//! ```rust,no_run
+//! use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+//! use tsnet::{http::Connector, ServerBuilder};
+//!
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! let ts = ServerBuilder::new().ephemeral().build()?;
+//! let client: Client<_, String> = Client::builder(TokioExecutor::new()).build(Connector::new(ts));
+//! let res = client.get("http://machine-name/".parse()?).await?;
+//! println!("{}", res.status());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{error::Error as StdError, future::Future, pin::pin, time::Duration};
+
+use hyper::{
+    body::{Body, Incoming},
+    service::Service,
+    Request, Response,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto, graceful::GracefulShutdown},
+};
+
+pub use crate::connector::Connector;
+#[cfg(feature = "reqwest")]
+use crate::Server;
+use crate::{AsyncListener, Error, Result};
+
+/// How long to pause accepting after an error, which is likely to happen again right away.
+const ACCEPT_ERROR_PAUSE: Duration = Duration::from_secs(1);
+
+/// Serve HTTP/1 and HTTP/2 connections accepted by `listener` with `service`.
+///
+/// Runs until the server of the listener is closed, then waits for the connections being
+/// served to finish. See [`serve_with_shutdown`] to stop serving on demand.
+///
+/// Errors accepting connections, like running out of file descriptors, are logged and
+/// retried after a pause, rather than stopping the server.
+pub async fn serve<S, B>(listener: AsyncListener, service: S) -> Result<()>
+where
+    S: Service<Request<Incoming>, Response = Response<B>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn StdError + Send + Sync>>,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn StdError + Send + Sync>>,
+{
+    serve_with_shutdown(listener, service, std::future::pending()).await
+}
+
+/// Like [`serve`], but also shuts down gracefully once `signal` completes.
+///
+/// Shutting down stops accepting connections, lets the requests in flight finish, and then
+/// closes the connections.
+pub async fn serve_with_shutdown<S, B, F>(
+    listener: AsyncListener,
+    service: S,
+    signal: F,
+) -> Result<()>
+where
+    S: Service<Request<Incoming>, Response = Response<B>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn StdError + Send + Sync>>,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn StdError + Send + Sync>>,
+    F: Future<Output = ()>,
+{
+    let builder = auto::Builder::new(TokioExecutor::new());
+    let graceful = GracefulShutdown::new();
+    let mut signal = pin!(signal);
+
+    loop {
+        let stream = tokio::select! {
+            stream = listener.accept() => stream,
+            () = &mut signal => break,
+        };
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(Error::ServerClosed) => break,
+            Err(err) => {
+                log::error!("error accepting http connection: {err}");
+                tokio::select! {
+                    () = tokio::time::sleep(ACCEPT_ERROR_PAUSE) => continue,
+                    () = &mut signal => break,
+                }
+            }
+        };
+
+        let conn = builder
+            .serve_connection(TokioIo::new(stream), service.clone())
+            .into_owned();
+        let conn = graceful.watch(conn);
+        tokio::spawn(async move {
+            if let Err(err) = conn.await {
+                log::debug!("error serving http connection: {err}");
+            }
+        });
+    }
+
+    graceful.shutdown().await;
+    Ok(())
+}
+
+/// A [`reqwest::Proxy`] sending all requests over the tailnet, through the SOCKS5 proxy of
+/// the loopback server of `ts`.
+///
+/// Host names are resolved by the node, so MagicDNS names work.
+/// Requires the `reqwest` feature. See [`Server::loopback`].
+#[cfg(feature = "reqwest")]
+pub fn reqwest_proxy(ts: &Server) -> Result<reqwest::Proxy> {
+    // socks5h, so that names are resolved by the proxy rather than locally.
+    let url = ts
+        .loopback()?
+        .proxy_url()
+        .replacen("socks5://", "socks5h://", 1);
+    reqwest::Proxy::all(url).map_err(|err| Error::IO(std::io::Error::other(err)))
+}
+
+/// A [`reqwest::Client`] making all requests over the tailnet, with [`reqwest_proxy`].
+///
+/// Requires the `reqwest` feature.
+#[cfg(feature = "reqwest")]
+pub fn reqwest_client(ts: &Server) -> Result<reqwest::Client> {
+    reqwest::Client::builder()
+        .proxy(reqwest_proxy(ts)?)
+        .build()
+        .map_err(|err| Error::IO(std::io::Error::other(err)))
+}
@@ -0,0 +1,488 @@
+//! Moving the identity of a node to another host.
+//!
+//! Bundles need the `identity` feature. Refusing to start with an identity that was exported
+//! doesn't, so that builds without it still honor bundles exported by builds with it.
+
+use std::ffi::{c_char, CString};
+#[cfg(feature = "identity")]
+use std::{
+    collections::BTreeMap,
+    fmt, io,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[cfg(feature = "identity")]
+use base64::{engine::general_purpose::STANDARD, Engine};
+#[cfg(feature = "identity")]
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Nonce,
+};
+#[cfg(feature = "identity")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "identity")]
+use sha2::Sha256;
+
+#[cfg(feature = "identity")]
+use crate::check;
+use crate::{call, sys, Error, Result};
+
+/// The version of the format of bundles.
+#[cfg(feature = "identity")]
+const VERSION: u32 = 1;
+
+/// The state keys of the identity of a node, besides its profile, named by
+/// [`CURRENT_PROFILE`].
+#[cfg(feature = "identity")]
+const MACHINE_KEY: &str = "_machinekey";
+#[cfg(feature = "identity")]
+const CURRENT_PROFILE: &str = "_current-profile";
+#[cfg(feature = "identity")]
+const PROFILES: &str = "_profiles";
+
+/// Set in the state of a node once its identity has been exported.
+const EXPORTED: &str = "_libtailscale-exported";
+
+#[cfg(feature = "identity")]
+const KDF: &str = "pbkdf2-sha256";
+#[cfg(feature = "identity")]
+const PBKDF2_ROUNDS: u32 = 600_000;
+/// The most rounds a bundle may ask for, so that importing one can't take forever.
+#[cfg(feature = "identity")]
+const MAX_PBKDF2_ROUNDS: u32 = 4 * PBKDF2_ROUNDS;
+#[cfg(feature = "identity")]
+const SALT_LEN: usize = 16;
+
+/// The identity of a node, as exported by
+/// [`Server::export_identity`](crate::Server::export_identity), to be imported on another
+/// host with [`ServerBuilder::import_identity`](crate::ServerBuilder::import_identity).
+///
+/// That's the machine key, the node key and the login profile of the node, so that the
+/// node keeps its name, tailnet IPs and ACL tags. The bundle holds the private keys of the
+/// node: keep it secret, or encrypt it with [`IdentityBundle::to_encrypted_bytes`].
+///
+/// Requires the `identity` feature.
+#[cfg(feature = "identity")]
+#[derive(Clone)]
+pub struct IdentityBundle {
+    entries: BTreeMap<String, Vec<u8>>,
+}
+
+/// The serialized form of a bundle, holding either the entries or their encryption.
+#[cfg(feature = "identity")]
+#[derive(Serialize, Deserialize)]
+struct Blob {
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    entries: Option<BTreeMap<String, String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encrypted: Option<Encrypted>,
+}
+
+/// The entries of a bundle in JSON, encrypted with ChaCha20-Poly1305 under a key derived
+/// from a passphrase.
+#[cfg(feature = "identity")]
+#[derive(Serialize, Deserialize)]
+struct Encrypted {
+    kdf: String,
+    rounds: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[cfg(feature = "identity")]
+impl IdentityBundle {
+    /// Serialize the bundle, in plaintext.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let blob = Blob {
+            version: VERSION,
+            entries: Some(self.encode_entries()),
+            encrypted: None,
+        };
+        serde_json::to_vec(&blob).unwrap()
+    }
+
+    /// Serialize the bundle, encrypted with `passphrase`.
+    pub fn to_encrypted_bytes(&self, passphrase: &str) -> Vec<u8> {
+        self.encrypt(passphrase, PBKDF2_ROUNDS)
+    }
+
+    fn encrypt(&self, passphrase: &str, rounds: u32) -> Vec<u8> {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let cipher = cipher(passphrase, &salt, rounds);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let plaintext = serde_json::to_vec(&self.encode_entries()).unwrap();
+        let ciphertext = cipher.encrypt(&nonce, plaintext.as_slice()).unwrap();
+
+        let blob = Blob {
+            version: VERSION,
+            entries: None,
+            encrypted: Some(Encrypted {
+                kdf: KDF.to_string(),
+                rounds,
+                salt: STANDARD.encode(salt),
+                nonce: STANDARD.encode(nonce),
+                ciphertext: STANDARD.encode(ciphertext),
+            }),
+        };
+        serde_json::to_vec(&blob).unwrap()
+    }
+
+    /// Deserialize a bundle, decrypting it with `passphrase` if it is encrypted.
+    pub fn from_bytes(bytes: &[u8], passphrase: Option<&str>) -> Result<Self> {
+        let blob: Blob = serde_json::from_slice(bytes)?;
+        if blob.version != VERSION {
+            return Err(invalid(format!(
+                "unsupported identity bundle version {}",
+                blob.version
+            )));
+        }
+
+        let entries = match (blob.entries, blob.encrypted, passphrase) {
+            (Some(entries), None, _) => entries,
+            (None, Some(_), None) => {
+                return Err(Error::IO(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "the identity bundle is encrypted, it needs a passphrase",
+                )))
+            }
+            (None, Some(encrypted), Some(passphrase)) => {
+                if encrypted.kdf != KDF {
+                    return Err(invalid(format!("unsupported kdf {}", encrypted.kdf)));
+                }
+                if !(1..=MAX_PBKDF2_ROUNDS).contains(&encrypted.rounds) {
+                    return Err(invalid(format!(
+                        "unsupported number of kdf rounds {}",
+                        encrypted.rounds
+                    )));
+                }
+                let salt = decode(&encrypted.salt)?;
+                let nonce = decode(&encrypted.nonce)?;
+                if nonce.len() != 12 {
+                    return Err(invalid("invalid nonce".to_string()));
+                }
+                let cipher = cipher(passphrase, &salt, encrypted.rounds);
+                let plaintext = cipher
+                    .decrypt(
+                        Nonce::from_slice(&nonce),
+                        decode(&encrypted.ciphertext)?.as_slice(),
+                    )
+                    .map_err(|_| {
+                        invalid(
+                            "can't decrypt the identity bundle, is the passphrase wrong?".into(),
+                        )
+                    })?;
+                serde_json::from_slice(&plaintext)?
+            }
+            _ => return Err(invalid("invalid identity bundle".to_string())),
+        };
+
+        let entries = entries
+            .into_iter()
+            .map(|(key, value)| Ok((key, decode(&value)?)))
+            .collect::<Result<_>>()?;
+        Ok(IdentityBundle { entries })
+    }
+
+    fn encode_entries(&self) -> BTreeMap<String, String> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.clone(), STANDARD.encode(value)))
+            .collect()
+    }
+}
+
+/// Only shows the state keys in the bundle, not the secrets.
+#[cfg(feature = "identity")]
+impl fmt::Debug for IdentityBundle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IdentityBundle")
+            .field("keys", &self.entries.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(feature = "identity")]
+fn cipher(passphrase: &str, salt: &[u8], rounds: u32) -> ChaCha20Poly1305 {
+    let mut key = [0; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, rounds, &mut key);
+    ChaCha20Poly1305::new(&key.into())
+}
+
+#[cfg(feature = "identity")]
+fn decode(value: &str) -> Result<Vec<u8>> {
+    STANDARD
+        .decode(value)
+        .map_err(|err| invalid(format!("invalid base64: {err}")))
+}
+
+#[cfg(feature = "identity")]
+fn invalid(msg: String) -> Error {
+    Error::InvalidIdentityBundle(msg)
+}
+
+/// The state of a node, which identities are exported from and imported into.
+pub(crate) trait NodeState {
+    /// Read the value of `key`, or `None` if there is none.
+    fn get_state(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Store `value` as the value of `key`.
+    #[cfg(feature = "identity")]
+    fn set_state(&self, key: &str, value: &[u8]) -> Result<()>;
+}
+
+/// The state of the server with this handle, see `tailscale_get_state`.
+impl NodeState for sys::tailscale {
+    fn get_state(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let key = CString::new(key)?;
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let mut len = 0;
+            match call(|error| unsafe {
+                sys::tailscale_get_state_err(
+                    *self,
+                    key.as_ptr(),
+                    buf.as_mut_ptr() as *mut c_char,
+                    buf.len(),
+                    &mut len,
+                    error,
+                )
+            }) {
+                Ok(()) => {
+                    buf.truncate(len);
+                    return Ok(Some(buf));
+                }
+                Err((nix::libc::ENOENT, _)) => return Ok(None),
+                Err((nix::libc::ERANGE, _)) if len > buf.len() => buf.resize(len, 0),
+                Err((_, e)) => return Err(e),
+            }
+        }
+    }
+
+    #[cfg(feature = "identity")]
+    fn set_state(&self, key: &str, value: &[u8]) -> Result<()> {
+        let key = CString::new(key)?;
+        check(|error| unsafe {
+            sys::tailscale_set_state_err(
+                *self,
+                key.as_ptr(),
+                value.as_ptr() as *const c_char,
+                value.len(),
+                error,
+            )
+        })
+    }
+}
+
+/// Read the identity from the state of a node, and mark it exported.
+#[cfg(feature = "identity")]
+pub(crate) fn export(state: impl NodeState) -> Result<IdentityBundle> {
+    let profile = state
+        .get_state(CURRENT_PROFILE)?
+        .filter(|profile| !profile.is_empty())
+        .ok_or_else(|| Error::TSNet("the node has no identity yet, log it in first".into()))?;
+    let profile_key = String::from_utf8(profile.clone()).map_err(|_| Error::CantConvertToString)?;
+
+    let mut entries = BTreeMap::new();
+    for key in [MACHINE_KEY, PROFILES, &profile_key] {
+        let value = state
+            .get_state(key)?
+            .ok_or_else(|| Error::TSNet(format!("the state of the node has no {key}")))?;
+        entries.insert(key.to_string(), value);
+    }
+    entries.insert(CURRENT_PROFILE.to_string(), profile);
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    state.set_state(EXPORTED, now.to_string().as_bytes())?;
+    Ok(IdentityBundle { entries })
+}
+
+/// Export the identity from the state of a node, and then close the node with `close`.
+///
+/// The bundle is returned even if closing fails, as the state is marked exported by then.
+#[cfg(feature = "identity")]
+pub(crate) fn hand_over(
+    state: impl NodeState,
+    close: impl FnOnce() -> Result<()>,
+) -> Result<IdentityBundle> {
+    let identity = export(state)?;
+    if let Err(err) = close() {
+        log::error!("can't close the server after exporting its identity: {err}");
+    }
+    Ok(identity)
+}
+
+/// Write the identity into the state of a node, which isn't started yet.
+#[cfg(feature = "identity")]
+pub(crate) fn import(state: impl NodeState, identity: &IdentityBundle) -> Result<()> {
+    for (key, value) in &identity.entries {
+        state.set_state(key, value)?;
+    }
+    state.set_state(EXPORTED, &[])
+}
+
+/// Refuse to start a server with an identity that was exported, and may be in use by
+/// another node by now.
+pub(crate) fn check_not_exported(state: impl NodeState) -> Result<()> {
+    match state.get_state(EXPORTED)? {
+        Some(exported) if !exported.is_empty() => Err(Error::InvalidConfig(
+            "the identity in this state was exported to another node, import it back to \
+             use it here"
+                .into(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(all(test, feature = "identity"))]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    #[derive(Default)]
+    struct MemState(RefCell<BTreeMap<String, Vec<u8>>>);
+
+    impl NodeState for &MemState {
+        fn get_state(&self, key: &str) -> Result<Option<Vec<u8>>> {
+            Ok(self.0.borrow().get(key).cloned())
+        }
+
+        fn set_state(&self, key: &str, value: &[u8]) -> Result<()> {
+            self.0.borrow_mut().insert(key.into(), value.into());
+            Ok(())
+        }
+    }
+
+    fn logged_in() -> MemState {
+        let state = MemState::default();
+        for (key, value) in [
+            (MACHINE_KEY, "privkey:machine"),
+            (CURRENT_PROFILE, "profile-1234"),
+            (PROFILES, "{}"),
+            ("profile-1234", "{\"node\":\"privkey:node\"}"),
+            ("_other", "not part of the identity"),
+        ] {
+            (&state).set_state(key, value.as_bytes()).unwrap();
+        }
+        state
+    }
+
+    fn bundle() -> IdentityBundle {
+        export(&logged_in()).unwrap()
+    }
+
+    /// Encrypted with few rounds, as the real number is slow without optimizations.
+    fn encrypted(passphrase: &str) -> Vec<u8> {
+        bundle().encrypt(passphrase, 1000)
+    }
+
+    /// Edit the encrypted part of a serialized bundle.
+    fn edit_encrypted(bytes: &[u8], f: impl FnOnce(&mut Encrypted)) -> Vec<u8> {
+        let mut blob: Blob = serde_json::from_slice(bytes).unwrap();
+        f(blob.encrypted.as_mut().unwrap());
+        serde_json::to_vec(&blob).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let identity = bundle();
+        assert_eq!(
+            identity.entries.keys().collect::<Vec<_>>(),
+            [CURRENT_PROFILE, MACHINE_KEY, PROFILES, "profile-1234"]
+        );
+
+        let plain = IdentityBundle::from_bytes(&identity.to_bytes(), None).unwrap();
+        assert_eq!(plain.entries, identity.entries);
+
+        let bytes = identity.encrypt("correct horse", 1000);
+        assert!(!bytes.windows(7).any(|w| w == b"privkey"));
+        let decrypted = IdentityBundle::from_bytes(&bytes, Some("correct horse")).unwrap();
+        assert_eq!(decrypted.entries, identity.entries);
+
+        let err = IdentityBundle::from_bytes(&bytes, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let bytes = encrypted("correct horse");
+        let err = IdentityBundle::from_bytes(&bytes, Some("battery staple")).unwrap_err();
+        assert!(matches!(err, Error::InvalidIdentityBundle(_)), "{err}");
+    }
+
+    #[test]
+    fn tampered_blob() {
+        let bytes = encrypted("correct horse");
+        let tampered = edit_encrypted(&bytes, |encrypted| {
+            let mut ciphertext = STANDARD.decode(&encrypted.ciphertext).unwrap();
+            ciphertext[0] ^= 1;
+            encrypted.ciphertext = STANDARD.encode(ciphertext);
+        });
+        let err = IdentityBundle::from_bytes(&tampered, Some("correct horse")).unwrap_err();
+        assert!(matches!(err, Error::InvalidIdentityBundle(_)), "{err}");
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut blob: Blob = serde_json::from_slice(&bundle().to_bytes()).unwrap();
+        blob.version = VERSION + 1;
+        let err =
+            IdentityBundle::from_bytes(&serde_json::to_vec(&blob).unwrap(), None).unwrap_err();
+        assert!(matches!(err, Error::InvalidIdentityBundle(_)), "{err}");
+    }
+
+    #[test]
+    fn rounds_out_of_range() {
+        let bytes = encrypted("correct horse");
+        for rounds in [0, MAX_PBKDF2_ROUNDS + 1, u32::MAX] {
+            let edited = edit_encrypted(&bytes, |encrypted| encrypted.rounds = rounds);
+            let err = IdentityBundle::from_bytes(&edited, Some("correct horse")).unwrap_err();
+            assert!(
+                err.to_string().contains("kdf rounds"),
+                "{rounds} rounds: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn exported_identity_is_refused_until_imported_back() {
+        let state = logged_in();
+        check_not_exported(&state).unwrap();
+        let identity = export(&state).unwrap();
+        let err = check_not_exported(&state).unwrap_err();
+        assert!(matches!(err, Error::InvalidConfig(_)), "{err}");
+
+        // It can be used where it is imported, including back here.
+        let other = MemState::default();
+        import(&other, &identity).unwrap();
+        check_not_exported(&other).unwrap();
+        import(&state, &identity).unwrap();
+        check_not_exported(&state).unwrap();
+    }
+
+    #[test]
+    fn hand_over_returns_identity_when_close_fails() {
+        let state = logged_in();
+        let identity = hand_over(&state, || Err(Error::ServerClosed)).unwrap();
+        assert!(identity.entries.contains_key(MACHINE_KEY));
+        assert!(check_not_exported(&state).is_err());
+
+        // Nothing is exported, nor closed, if the node was never logged in.
+        let state = MemState::default();
+        let err = hand_over(&state, || panic!("closed")).unwrap_err();
+        assert!(matches!(err, Error::TSNet(_)), "{err}");
+        check_not_exported(&state).unwrap();
+    }
+
+    #[test]
+    fn export_needs_login() {
+        let err = export(&MemState::default()).unwrap_err();
+        assert!(matches!(err, Error::TSNet(_)), "{err}");
+    }
+}
@@ -12,8 +12,7 @@
 //! ### Server
 //!
 //! ```rust,no_run
-//! use std::net::TcpStream;
-//! use tsnet::{ServerBuilder, Network};
+//! use tsnet::{Conn, ServerBuilder, Network};
 //!
 //! fn main() {
 //!     let ts = ServerBuilder::new().ephemeral().redirect_log().build().unwrap();
@@ -27,7 +26,7 @@
 //!     }
 //! }
 //!
-//! fn handle_client(mut stream: TcpStream) {
+//! fn handle_client(mut conn: Conn) {
 //!   // ...
 //! }
 //! ```
@@ -55,27 +54,58 @@
 #[allow(non_camel_case_types, dead_code)]
 mod sys;
 
+mod accept;
+#[cfg(feature = "async-io")]
+pub mod async_io;
+#[cfg(feature = "axum")]
+pub mod axum;
+#[cfg(any(feature = "http", feature = "tonic"))]
+mod connector;
+#[cfg(feature = "http")]
+pub mod http;
+mod identity;
+pub mod local;
+mod loopback;
+mod packet;
+mod state;
+#[cfg(feature = "rustls")]
+pub mod tls;
+#[cfg(feature = "tonic")]
+pub mod tonic;
+
 use std::{
-    ffi::{c_int, CStr, CString},
+    ffi::{c_char, c_int, CStr, CString},
     fmt::Display,
     fs::File,
-    io::Read,
-    net::TcpStream,
-    os::fd::FromRawFd,
+    io::{self, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream},
+    ops::{Deref, DerefMut},
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
     path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     thread,
+    time::Duration,
 };
 #[cfg(feature = "tokio")]
 use std::{
-    future::Future,
+    future::poll_fn,
+    os::unix::net::UnixStream,
     pin::Pin,
     task::{Context, Poll},
 };
 
 #[cfg(feature = "tokio")]
-use hyper::server::accept::Accept;
+use futures_core::Stream;
+#[cfg(feature = "rustls")]
+use rustls_pki_types::{CertificateDer, PrivateKeyDer};
 #[cfg(feature = "tokio")]
-use tokio::{net, task};
+use tokio::{
+    io::{unix::AsyncFd, AsyncRead, AsyncWrite, ReadBuf},
+    net,
+};
 
 /// Possible errors
 #[derive(Debug, thiserror::Error)]
@@ -92,11 +122,108 @@ pub enum Error {
     #[error("io error: {0}")]
     IO(#[from] std::io::Error),
 
+    /// The LocalAPI responded with an error.
+    #[error("localapi: {status}: {message}")]
+    LocalApi {
+        /// The HTTP status code of the response.
+        status: u16,
+        /// The error message from the response body.
+        message: String,
+    },
+
+    /// A response from `libtailscale` could not be decoded.
+    #[error("can't decode response: {0}")]
+    Decode(#[from] serde_json::Error),
+
     /// Data passed in contained NULL bytes.
     #[error("your string has NULL in it: {0}")]
     NullInString(#[from] std::ffi::NulError),
+
+    /// The server was closed, along with its listeners and connections.
+    #[error("the server was closed")]
+    ServerClosed,
+
+    /// The operation timed out, like waiting for the node to come up.
+    #[error("timed out")]
+    Timeout,
+
+    /// The node must be logged in interactively before it can be used.
+    ///
+    /// See the login URL in the tsnet log, or use an auth key.
+    #[error("the node needs to be logged in: {0}")]
+    NeedsLogin(String),
+
+    /// The auth key was rejected by the control server, or has expired.
+    #[error("invalid auth key: {0}")]
+    AuthKeyInvalid(String),
+
+    /// The control server could not be reached.
+    #[error("can't reach the control server: {0}")]
+    ControlUnreachable(String),
+
+    /// The remote peer refused the connection.
+    #[error("connection refused: {0}")]
+    ConnectionRefused(String),
+
+    /// The address is already being listened on.
+    #[error("address in use: {0}")]
+    AddrInUse(String),
+
+    /// The handle doesn't refer to a server, as it was never created or has been closed.
+    #[error("unknown server handle")]
+    UnknownHandle,
+
+    /// The options of a [`ServerBuilder`] don't go together.
+    #[error("invalid configuration: {0}")]
+    InvalidConfig(String),
+
+    /// An [`IdentityBundle`] can't be read, as it is corrupt or of an unsupported format.
+    #[cfg(feature = "identity")]
+    #[error("invalid identity bundle: {0}")]
+    InvalidIdentityBundle(String),
+}
+
+impl Error {
+    /// The closest [`io::ErrorKind`] for this error.
+    pub fn kind(&self) -> io::ErrorKind {
+        match self {
+            Error::IO(err) => err.kind(),
+            Error::CantConvertToString
+            | Error::NullInString(_)
+            | Error::UnknownHandle
+            | Error::InvalidConfig(_) => io::ErrorKind::InvalidInput,
+            Error::Decode(_) => io::ErrorKind::InvalidData,
+            #[cfg(feature = "identity")]
+            Error::InvalidIdentityBundle(_) => io::ErrorKind::InvalidData,
+            Error::ServerClosed => io::ErrorKind::NotConnected,
+            Error::Timeout => io::ErrorKind::TimedOut,
+            Error::NeedsLogin(_) | Error::AuthKeyInvalid(_) => io::ErrorKind::PermissionDenied,
+            Error::ControlUnreachable(_) => io::ErrorKind::HostUnreachable,
+            Error::ConnectionRefused(_) => io::ErrorKind::ConnectionRefused,
+            Error::AddrInUse(_) => io::ErrorKind::AddrInUse,
+            Error::TSNet(_) | Error::LocalApi { .. } => io::ErrorKind::Other,
+        }
+    }
 }
 
+/// Converts to an [`io::Error`] of the matching [`Error::kind`], keeping IO errors as they are.
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::IO(err) => err,
+            err => io::Error::new(err.kind(), err),
+        }
+    }
+}
+
+#[cfg(feature = "identity")]
+pub use identity::IdentityBundle;
+pub use loopback::{Loopback, Secret};
+pub use packet::PacketSocket;
+#[cfg(feature = "encrypted-state")]
+pub use state::EncryptedStore;
+pub use state::StateStore;
+
 /// A Result, returning either a value or an error, defaulting to the crate error.
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -107,7 +234,8 @@ pub enum Network {
     Tcp,
     /// UDP.
     ///
-    /// Note: UDP currently not really tested.
+    /// Note: connections and listeners are streams, so datagram boundaries are lost.
+    /// Use [`Server::listen_packet`] for datagrams.
     Udp,
 }
 
@@ -124,6 +252,13 @@ impl Display for Network {
 ///
 /// Can act as a server or client.
 ///
+/// Cloning the server is cheap, and gives another handle onto the same node, which can be
+/// moved to other threads. The node is closed by [`Server::close`] or [`Server::shutdown`],
+/// or once the last server handle, listener, packet socket and connection has been dropped.
+///
+/// Connections, a [`Conn`] or an [`IncomingConn`], keep the node alive, but not their bare
+/// streams once taken out of them: those read EOF once the node is closed.
+///
 /// ## Example
 ///
 /// ```rust,no_run
@@ -136,85 +271,683 @@ impl Display for Network {
 ///   // handle connection
 /// }
 /// ```
+#[derive(Clone, Debug)]
 pub struct Server {
+    inner: Arc<Inner>,
+}
+
+/// The state shared by the handles onto a server, and by its listeners and connections.
+#[derive(Debug)]
+pub(crate) struct Inner {
     /// a handle onto a Tailscale server
     handle: sys::tailscale,
+    /// whether the handle was closed, after which it must not be used
+    closed: AtomicBool,
+    /// the state store the server calls back into, kept alive until the server is dropped
+    store: Option<state::Registered>,
 }
 
-fn err(handle: c_int, code: c_int) -> Result<(), Error> {
-    if code < 0 {
-        unsafe {
-            let mut errmsg: [i8; 256] = [0; 256];
-            let res = sys::tailscale_errmsg(handle, &mut errmsg as *mut _, errmsg.len());
-            if res != 0 {
-                panic!("tailscale_errmsg returned {res}");
+impl Inner {
+    /// The handle onto the Tailscale server, unless it has been closed.
+    pub(crate) fn handle(&self) -> Result<sys::tailscale> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(Error::ServerClosed);
+        }
+        Ok(self.handle)
+    }
+
+    /// Like [`call`], but reporting errors from the server having been closed concurrently
+    /// as [`Error::ServerClosed`].
+    fn call(
+        &self,
+        f: impl FnOnce(*mut sys::tailscale_error) -> c_int,
+    ) -> Result<(), (c_int, Error)> {
+        call(f).map_err(|(res, e)| match e {
+            Error::UnknownHandle if self.closed.load(Ordering::Acquire) => {
+                (res, Error::ServerClosed)
             }
+            e => (res, e),
+        })
+    }
+
+    /// Like [`Inner::call`], for when only the error matters.
+    fn check(&self, f: impl FnOnce(*mut sys::tailscale_error) -> c_int) -> Result<()> {
+        self.call(f).map_err(|(_, e)| e)
+    }
+
+    /// Mark the server closed, returning whether it was open until now.
+    fn close(&self) -> bool {
+        !self.closed.swap(true, Ordering::AcqRel)
+    }
+}
 
-            let slice = CStr::from_ptr(&errmsg as *const _);
-            let errmsg = String::from_utf8_lossy(slice.to_bytes()).to_string();
-            Err(Error::TSNet(errmsg))
+/// The server is closed once nothing refers to it anymore, unless it was closed already.
+impl Drop for Inner {
+    fn drop(&mut self) {
+        if self.close() {
+            unsafe {
+                sys::tailscale_close(self.handle);
+            }
         }
-    } else {
-        Ok(())
+    }
+}
+
+// Servers and their listeners are shared and moved between threads.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Server>();
+    assert_send_sync::<Listener>();
+    assert_send_sync::<IncomingConn>();
+    assert_send_sync::<Conn>();
+};
+
+// Builders are configured on one thread and built on another.
+const _: fn() = || {
+    fn assert_send<T: Send>() {}
+    assert_send::<ServerBuilder>();
+};
+
+// Futures are spawned onto multi-threaded runtimes.
+#[cfg(feature = "tokio")]
+const _: fn(&Server) = |ts| {
+    fn assert_send<T: Send>(_: T) {}
+    assert_send(ts.up_async());
+    assert_send(ts.connect_async(Network::Tcp, ""));
+};
+
+/// Call `f` with a buffer to write a string to, growing the buffer as long as `f` reports
+/// it is too small by returning `ERANGE`.
+///
+/// `f` is one of the `_err` variants of the libtailscale functions, see [`call`].
+fn read_string(
+    mut f: impl FnMut(*mut c_char, usize, *mut sys::tailscale_error) -> c_int,
+) -> Result<String, Error> {
+    const MAX_LEN: usize = 16 << 20;
+
+    let mut buf = vec![0 as c_char; 4096];
+    loop {
+        match call(|error| f(buf.as_mut_ptr(), buf.len(), error)) {
+            Err((nix::libc::ERANGE, _)) if buf.len() < MAX_LEN => {
+                buf.resize(buf.len() * 2, 0);
+            }
+            Err((nix::libc::ERANGE, _)) => {
+                return Err(Error::TSNet(format!(
+                    "response is larger than {MAX_LEN} bytes"
+                )));
+            }
+            Err((_, e)) => return Err(e),
+            Ok(()) => {
+                let s = unsafe { CStr::from_ptr(buf.as_ptr()) };
+                return Ok(s.to_string_lossy().into_owned());
+            }
+        }
+    }
+}
+
+fn whois(handle: sys::tailscale, addr: &str) -> Result<local::WhoIs> {
+    let addr = CString::new(addr)?;
+    let who = read_string(|buf, len, error| unsafe {
+        sys::tailscale_whois_err(handle, addr.as_ptr(), buf, len, error)
+    })?;
+    Ok(serde_json::from_str(&who)?)
+}
+
+fn cert_domains(handle: sys::tailscale) -> Result<Vec<String>> {
+    let domains = read_string(|buf, len, error| unsafe {
+        sys::tailscale_cert_domains_err(handle, buf, len, error)
+    })?;
+    Ok(domains
+        .split(',')
+        .filter(|domain| !domain.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+#[cfg(feature = "rustls")]
+fn cert_pair(
+    handle: sys::tailscale,
+    domain: &str,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let domain = CString::new(domain)?;
+    let pem = read_string(|buf, len, error| unsafe {
+        sys::tailscale_cert_pair_err(handle, domain.as_ptr(), buf, len, error)
+    })?;
+
+    let certs = rustls_pemfile::certs(&mut pem.as_bytes()).collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut pem.as_bytes())?
+        .ok_or_else(|| Error::TSNet("no private key in certificate pair".to_string()))?;
+    Ok((certs, key))
+}
+
+/// Call one of the `_err` variants of the libtailscale functions, passing `f` where to put
+/// the error of this particular call.
+///
+/// Returns the non-zero result of the call along with its error.
+fn call(f: impl FnOnce(*mut sys::tailscale_error) -> c_int) -> Result<(), (c_int, Error)> {
+    let mut error = 0;
+    match f(&mut error) {
+        0 => Ok(()),
+        res => Err((res, take_error(error))),
+    }
+}
+
+/// Like [`call`], for when only the error matters.
+fn check(f: impl FnOnce(*mut sys::tailscale_error) -> c_int) -> Result<()> {
+    call(f).map_err(|(_, e)| e)
+}
+
+/// Turn a `tailscale_error` into an [`Error`], classified by its code, and free it.
+fn take_error(error: sys::tailscale_error) -> Error {
+    let code = unsafe { sys::tailscale_error_code(error) };
+
+    let mut buf = vec![0 as c_char; 256];
+    while unsafe { sys::tailscale_error_msg(error, buf.as_mut_ptr(), buf.len()) }
+        == nix::libc::ERANGE
+    {
+        buf.resize(buf.len() * 2, 0);
+    }
+    let msg = unsafe { CStr::from_ptr(buf.as_ptr()) }
+        .to_string_lossy()
+        .into_owned();
+    unsafe { sys::tailscale_error_free(error) };
+
+    error_from_code(code, msg)
+}
+
+/// The [`Error`] for a `TAILSCALE_ERR_*` code and message.
+fn error_from_code(code: c_int, msg: String) -> Error {
+    match code as u32 {
+        sys::TAILSCALE_ERR_NEEDS_LOGIN => Error::NeedsLogin(msg),
+        sys::TAILSCALE_ERR_AUTHKEY_INVALID => Error::AuthKeyInvalid(msg),
+        sys::TAILSCALE_ERR_CONTROL_UNREACHABLE => Error::ControlUnreachable(msg),
+        sys::TAILSCALE_ERR_CONN_REFUSED => Error::ConnectionRefused(msg),
+        sys::TAILSCALE_ERR_TIMEOUT => Error::Timeout,
+        sys::TAILSCALE_ERR_ADDR_IN_USE => Error::AddrInUse(msg),
+        sys::TAILSCALE_ERR_UNKNOWN_HANDLE => Error::UnknownHandle,
+        sys::TAILSCALE_ERR_SERVER_CLOSED => Error::ServerClosed,
+        _ => Error::TSNet(msg),
     }
 }
 
 impl Server {
+    /// Connect the node to the tailnet and wait for it to be usable.
+    ///
+    /// Starts the server if needed, then blocks until the node has been assigned its
+    /// addresses and received its network map, such that connecting and listening will
+    /// work right away.
+    ///
+    /// Returns the state the node is up in, see [`Server::up_state`].
+    ///
+    /// See [`Server::up_with_timeout`] to bound the wait.
+    pub fn up(&self) -> Result<UpState> {
+        let handle = self.inner.handle()?;
+        self.inner
+            .check(|error| unsafe { sys::tailscale_up_err(handle, error) })?;
+        self.up_state()
+    }
+
+    /// Like [`Server::up`], but gives up waiting after `timeout`.
+    ///
+    /// Returns [`Error::Timeout`] if the node isn't usable in time, or more specifically
+    /// [`Error::NeedsLogin`] or [`Error::ControlUnreachable`] if that's what it was waiting on.
+    pub fn up_with_timeout(&self, timeout: Duration) -> Result<UpState> {
+        let handle = self.inner.handle()?;
+        let timeout_ms = c_int::try_from(timeout.as_millis()).unwrap_or(c_int::MAX);
+        self.inner
+            .call(|error| unsafe { sys::tailscale_up_timeout_err(handle, timeout_ms, error) })
+            .map_err(|(res, e)| match e {
+                Error::NeedsLogin(_) | Error::ControlUnreachable(_) => e,
+                _ if res == nix::libc::ETIMEDOUT => Error::Timeout,
+                e => e,
+            })?;
+        self.up_state()
+    }
+
+    /// Like [`Server::up`], but waits asynchronously.
+    ///
+    /// Dropping the future cancels waiting. Must be called from within a tokio runtime.
+    /// Requires the `tokio` feature.
+    #[cfg(feature = "tokio")]
+    pub async fn up_async(&self) -> Result<UpState> {
+        let handle = self.inner.handle()?;
+        self.op(|op, error| unsafe { sys::tailscale_up_start(handle, op, error) })
+            .await?;
+        self.up_state()
+    }
+
+    /// The state the node was in when it last came up, with [`Server::up`] or any of its
+    /// variants, including [`ServerBuilder::wait_for_up`].
+    ///
+    /// Fails if the node was never up. For the current state, see
+    /// [`LocalClient::status`](local::LocalClient::status).
+    pub fn up_state(&self) -> Result<UpState> {
+        let handle = self.inner.handle()?;
+        let status = read_string(|buf, len, error| unsafe {
+            sys::tailscale_up_status_err(handle, buf, len, error)
+        })?;
+        let status: local::Status = serde_json::from_str(&status)?;
+        Ok(UpState::from(status))
+    }
+
+    /// The tailnet addresses assigned to this node, if any.
+    ///
+    /// Addresses are only assigned once the node is up, see [`Server::up`].
+    pub fn tailscale_ips(&self) -> Result<(Option<Ipv4Addr>, Option<Ipv6Addr>)> {
+        let handle = self.inner.handle()?;
+        let mut buf = [0 as c_char; 256];
+        self.inner.check(|error| unsafe {
+            sys::tailscale_getips_err(handle, buf.as_mut_ptr(), buf.len(), error)
+        })?;
+
+        let ips = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy();
+        let (mut ip4, mut ip6) = (None, None);
+        for ip in ips.split(',').filter(|ip| !ip.is_empty()) {
+            match ip.parse::<IpAddr>() {
+                Ok(IpAddr::V4(ip)) => ip4 = ip4.or(Some(ip)),
+                Ok(IpAddr::V6(ip)) => ip6 = ip6.or(Some(ip)),
+                Err(_) => return Err(Error::TSNet(format!("invalid tailnet address: {ip}"))),
+            }
+        }
+        Ok((ip4, ip6))
+    }
+
+    /// Close the server, along with all its listeners and connections:
+    /// [`Listener::accept`] and [`PacketSocket::recv_from`] return [`Error::ServerClosed`],
+    /// and streams read EOF.
+    ///
+    /// This affects every clone of this server. Afterwards, using any of them fails with
+    /// [`Error::ServerClosed`]. Closing an already closed server does nothing.
+    ///
+    /// Without this, the server is closed once it, its clones, its listeners, packet sockets
+    /// and connections have all been dropped, see [`Server`].
+    pub fn close(&self) -> Result<()> {
+        if !self.inner.close() {
+            return Ok(());
+        }
+        if unsafe { sys::tailscale_close(self.inner.handle) } != 0 {
+            return Err(Error::TSNet(
+                "close failed, see the tsnet log for details".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Export the identity of the node, to move it to another host with
+    /// [`ServerBuilder::import_identity`].
+    ///
+    /// So that two nodes never share the identity, exporting hands it over: the identity
+    /// is marked as exported in the state of this node, and the server is closed as with
+    /// [`Server::close`]. Building a server with this state again fails with
+    /// [`Error::InvalidConfig`], unless the identity is imported back. That check needs the
+    /// state to be in [`ServerBuilder::dir`] or a [store](ServerBuilder::store).
+    ///
+    /// Fails if the node was never logged in. Once the identity is exported, it is returned
+    /// even if closing the server fails, as this node can't use it anymore: that error is
+    /// only logged.
+    ///
+    /// Requires the `identity` feature.
+    #[cfg(feature = "identity")]
+    pub fn export_identity(&self) -> Result<IdentityBundle> {
+        let handle = self.inner.handle()?;
+        identity::hand_over(handle, || self.close())
+    }
+
+    /// Gracefully shut down the server.
+    ///
+    /// In order, this:
+    ///
+    /// * cancels any in-progress [`Server::up`] or [`Server::connect`],
+    /// * closes all listeners, such that [`Listener::accept`] returns [`Error::ServerClosed`],
+    /// * waits up to `timeout` for open connections to finish,
+    /// * closes the connections still open,
+    /// * logs the node out if it is ephemeral, removing it from the tailnet right away,
+    /// * and finally closes the server as [`Server::close`] would.
+    ///
+    /// Returns a report of what had to be done.
+    /// Fails with [`Error::ServerClosed`] if the server was already closed, from any handle.
+    pub fn shutdown(self, timeout: Duration) -> Result<ShutdownReport> {
+        let handle = self.inner.handle()?;
+        if !self.inner.close() {
+            return Err(Error::ServerClosed);
+        }
+        let timeout_ms = c_int::try_from(timeout.as_millis()).unwrap_or(c_int::MAX);
+        let mut report = sys::tailscale_shutdown_report {
+            listeners_closed: 0,
+            conns_drained: 0,
+            conns_forced: 0,
+            logged_out: 0,
+        };
+
+        let res = unsafe { sys::tailscale_shutdown(handle, timeout_ms, &mut report) };
+        if res != 0 {
+            return Err(Error::TSNet(
+                "shutdown failed, see the tsnet log for details".to_string(),
+            ));
+        }
+
+        Ok(ShutdownReport {
+            listeners_closed: report.listeners_closed as usize,
+            connections_drained: report.conns_drained as usize,
+            connections_forced: report.conns_forced as usize,
+            logged_out: report.logged_out != 0,
+        })
+    }
+
     /// Connect to the given address over the specified network.
-    pub fn connect(&self, network: Network, addr: &str) -> Result<TcpStream> {
+    pub fn connect(&self, network: Network, addr: &str) -> Result<Conn> {
+        let handle = self.inner.handle()?;
         let mut conn: sys::tailscale_conn = 0;
         let network = CString::new(format!("{}", network)).unwrap();
         let addr = CString::new(addr)?;
 
-        unsafe {
-            err(
-                self.handle,
-                sys::tailscale_dial(self.handle, network.as_ptr(), addr.as_ptr(), &mut conn),
-            )?
-        }
+        self.inner.check(|error| unsafe {
+            sys::tailscale_dial_err(handle, network.as_ptr(), addr.as_ptr(), &mut conn, error)
+        })?;
 
         let conn = conn as c_int;
-        Ok(unsafe { TcpStream::from_raw_fd(conn) })
+        Ok(Conn::new(&self.inner, unsafe {
+            TcpStream::from_raw_fd(conn)
+        }))
+    }
+
+    /// Like [`Server::connect`], but connects asynchronously.
+    ///
+    /// Dropping the future cancels connecting. Must be called from within a tokio runtime.
+    /// Requires the `tokio` feature.
+    #[cfg(feature = "tokio")]
+    pub async fn connect_async(
+        &self,
+        network: Network,
+        addr: &str,
+    ) -> Result<Conn<net::TcpStream>> {
+        let handle = self.inner.handle()?;
+        let network = CString::new(format!("{}", network)).unwrap();
+        let addr = CString::new(addr)?;
+
+        let conn = self
+            .op(|op, error| unsafe {
+                sys::tailscale_dial_start(handle, network.as_ptr(), addr.as_ptr(), op, error)
+            })
+            .await?
+            .ok_or_else(|| Error::TSNet("dial returned no connection".to_string()))?;
+
+        let stream = TcpStream::from(conn);
+        stream.set_nonblocking(true)?;
+        Ok(Conn::new(&self.inner, net::TcpStream::from_std(stream)?))
+    }
+
+    /// Start an operation in the background with `start`, returning its `tailscale_op`.
+    #[cfg(any(feature = "tokio", feature = "async-io"))]
+    fn start_op(
+        &self,
+        start: impl FnOnce(*mut sys::tailscale_op, *mut sys::tailscale_error) -> c_int,
+    ) -> Result<OwnedFd> {
+        let mut op = 0;
+        self.inner.check(|error| start(&mut op, error))?;
+        Ok(unsafe { OwnedFd::from_raw_fd(op) })
+    }
+
+    /// Run an operation in the background with `start`, and wait for it to be done.
+    ///
+    /// Returns the connection the operation made, if any. Dropping the future closes the
+    /// `tailscale_op`, which cancels the operation.
+    #[cfg(feature = "tokio")]
+    async fn op(
+        &self,
+        start: impl FnOnce(*mut sys::tailscale_op, *mut sys::tailscale_error) -> c_int,
+    ) -> Result<Option<OwnedFd>> {
+        let op = async_fd(self.start_op(start)?)?;
+
+        poll_fn(|cx| loop {
+            let mut ready = match op.poll_read_ready(cx) {
+                Poll::Ready(ready) => ready?,
+                Poll::Pending => return Poll::Pending,
+            };
+            if let Ok(res) = ready.try_io(|fd| op_result(fd.get_ref().as_fd())) {
+                return Poll::Ready(res);
+            }
+        })
+        .await?
     }
 
     /// Listen on the given address and network for new connections.
     pub fn listen(&self, network: Network, address: &str) -> Result<Listener, Error> {
-        unsafe {
-            let network = CString::new(format!("{}", network)).unwrap();
-            let addr = CString::new(address).unwrap();
-            let mut out = 0;
-            let res = err(
-                self.handle,
-                sys::tailscale_listen(
-                    self.handle,
-                    network.as_ptr(),
-                    addr.as_ptr(),
-                    &mut out as *mut _,
-                ),
-            );
-
-            res.map(|_| Listener {
-                ts: self.handle,
-                handle: out,
-            })
-        }
+        let handle = self.inner.handle()?;
+        self.listen_with(network, address, |network, addr, out, error| unsafe {
+            sys::tailscale_listen_err(handle, network, addr, out, error)
+        })
     }
 
+    /// Listen on the given TCP address for new TLS connections.
+    ///
+    /// Every accepted connection is already TLS-terminated, with a certificate for this
+    /// node's name automatically provisioned from Let's Encrypt.
+    /// This requires HTTPS to be enabled on the tailnet; see [`Server::cert_domains`] for the
+    /// names that can be served.
+    pub fn listen_tls(&self, address: &str) -> Result<Listener, Error> {
+        let handle = self.inner.handle()?;
+        self.listen_with(Network::Tcp, address, |network, addr, out, error| unsafe {
+            sys::tailscale_listen_tls_err(handle, network, addr, out, error)
+        })
+    }
+
+    /// Listen on the given TCP address for new TLS connections, from the tailnet and from the
+    /// public internet via [Funnel](https://tailscale.com/kb/1223/funnel).
+    ///
+    /// Funnel must be allowed for this node by the tailnet policy, and the address must use one
+    /// of the ports Funnel supports (443, 8443 or 10000).
+    /// See [`IncomingConn::via_funnel`] to tell public connections apart.
+    pub fn listen_funnel(&self, address: &str, options: FunnelOptions) -> Result<Listener, Error> {
+        let handle = self.inner.handle()?;
+        let funnel_only = if options.funnel_only { 1 } else { 0 };
+        self.listen_with(Network::Tcp, address, |network, addr, out, error| unsafe {
+            sys::tailscale_listen_funnel_err(handle, network, addr, funnel_only, out, error)
+        })
+    }
+
+    /// The domain names this node can obtain TLS certificates for, and thus serve with
+    /// [`Server::listen_tls`].
+    ///
+    /// Empty if HTTPS is not enabled on the tailnet, or if the node is not up yet.
+    pub fn cert_domains(&self) -> Result<Vec<String>> {
+        let handle = self.inner.handle()?;
+        cert_domains(handle)
+    }
+
+    /// Obtain a TLS certificate chain and private key for the given domain, one of
+    /// [`Server::cert_domains`].
+    ///
+    /// The certificate is provisioned from Let's Encrypt on first use, then cached in the state
+    /// directory and renewed as needed, so this can be called again to pick up renewals.
+    /// This is for using the certificate with your own TLS stack; see also
+    /// [`Server::listen_tls`], and [`tls::CertResolver`] for a ready-made certificate resolver.
+    ///
+    /// Requires the `rustls` feature.
+    #[cfg(feature = "rustls")]
+    pub fn cert_pair(
+        &self,
+        domain: &str,
+    ) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+        let handle = self.inner.handle()?;
+        cert_pair(handle, domain)
+    }
+
+    fn listen_with(
+        &self,
+        network: Network,
+        address: &str,
+        listen: impl FnOnce(
+            *const c_char,
+            *const c_char,
+            *mut sys::tailscale_listener,
+            *mut sys::tailscale_error,
+        ) -> c_int,
+    ) -> Result<Listener, Error> {
+        let network = CString::new(format!("{}", network)).unwrap();
+        let addr = CString::new(address).unwrap();
+        let mut out = 0;
+        let res = self
+            .inner
+            .check(|error| listen(network.as_ptr(), addr.as_ptr(), &mut out as *mut _, error));
+
+        res.map(|_| Listener {
+            ts: self.inner.clone(),
+            handle: unsafe { OwnedFd::from_raw_fd(out) },
+        })
+    }
+
+    /// Listen for UDP datagrams on the given address.
+    ///
+    /// The address must be of the form `ip:port`, where `ip` is one of the tailnet addresses of
+    /// this node (see [`Server::tailscale_ips`]).
+    pub fn listen_packet(&self, address: &str) -> Result<PacketSocket> {
+        let handle = self.inner.handle()?;
+        let network = CString::new(format!("{}", Network::Udp)).unwrap();
+        let addr = CString::new(address)?;
+        let mut out = 0;
+
+        self.inner.check(|error| unsafe {
+            sys::tailscale_listen_packet_err(
+                handle,
+                network.as_ptr(),
+                addr.as_ptr(),
+                &mut out as *mut _,
+                error,
+            )
+        })?;
+        Ok(PacketSocket::new(
+            unsafe { OwnedFd::from_raw_fd(out) },
+            self.inner.clone(),
+        ))
+    }
+
+    /// Start the loopback server, if it isn't running yet, and return its address and credentials.
+    ///
+    /// The loopback server is a SOCKS5 proxy onto the tailnet and serves the LocalAPI,
+    /// which lets existing tools reach tailnet peers through this node.
+    /// See [`Loopback`] for details.
+    pub fn loopback(&self) -> Result<Loopback> {
+        let handle = self.inner.handle()?;
+        let mut addr = [0 as c_char; 256];
+        let mut proxy_cred = [0 as c_char; 33];
+        let mut local_api_cred = [0 as c_char; 33];
+
+        self.inner.check(|error| unsafe {
+            sys::tailscale_loopback_err(
+                handle,
+                addr.as_mut_ptr(),
+                addr.len(),
+                proxy_cred.as_mut_ptr(),
+                local_api_cred.as_mut_ptr(),
+                error,
+            )
+        })?;
+
+        let to_string = |buf: &[c_char]| unsafe {
+            String::from_utf8_lossy(CStr::from_ptr(buf.as_ptr()).to_bytes()).into_owned()
+        };
+        let addr = to_string(&addr);
+        let addr: SocketAddr = addr
+            .parse()
+            .map_err(|_| Error::TSNet(format!("invalid loopback address: {addr}")))?;
+
+        Ok(Loopback {
+            addr,
+            proxy_cred: Secret::new(to_string(&proxy_cred)),
+            local_api_cred: Secret::new(to_string(&local_api_cred)),
+        })
+    }
+
+    /// Look up the node and user owning the given tailnet address.
+    ///
+    /// `addr` can be an IP, or an IP and port, like [`IncomingConn::remote_addr`].
+    pub fn whois(&self, addr: &str) -> Result<local::WhoIs> {
+        let handle = self.inner.handle()?;
+        whois(handle, addr)
+    }
+
+    /// Get a client for the LocalAPI of this node, starting the loopback server if needed.
+    ///
+    /// See [`local::LocalClient`].
+    pub fn local_client(&self) -> Result<local::LocalClient> {
+        Ok(local::LocalClient::new(self.loopback()?))
+    }
+
+    /// Like [`Server::listen`], but for accepting connections asynchronously with tokio.
+    ///
+    /// Must be called from within a tokio runtime. Requires the `tokio` feature.
     #[cfg(feature = "tokio")]
     pub fn listen_async(&self, network: Network, address: &str) -> Result<AsyncListener, Error> {
-        let ls = self.listen(network, address)?;
-        Ok(AsyncListener {
-            listener: ls,
-            fut: None,
-        })
+        AsyncListener::new(self.listen(network, address)?)
     }
 }
 
-impl Drop for Server {
-    fn drop(&mut self) {
-        unsafe {
-            sys::tailscale_close(self.handle);
+/// The state of a node once it is up, as returned by [`Server::up`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UpState {
+    /// The IPv4 tailnet address of the node.
+    pub ipv4: Option<Ipv4Addr>,
+    /// The IPv6 tailnet address of the node.
+    pub ipv6: Option<Ipv6Addr>,
+    /// The hostname the node presents.
+    pub hostname: String,
+    /// The MagicDNS name of the node, with a trailing dot.
+    pub dns_name: String,
+    /// The MagicDNS suffix of the tailnet, like `tail1234.ts.net`.
+    pub magic_dns_suffix: String,
+    /// The ACL tags of the node.
+    pub tags: Vec<String>,
+    /// The names the node can obtain TLS certificates for.
+    pub cert_domains: Vec<String>,
+}
+
+impl From<local::Status> for UpState {
+    fn from(status: local::Status) -> Self {
+        let node = status.self_status.unwrap_or_default();
+        let mut state = UpState {
+            ipv4: None,
+            ipv6: None,
+            hostname: node.host_name,
+            dns_name: node.dns_name,
+            magic_dns_suffix: status.magic_dns_suffix,
+            tags: node.tags,
+            cert_domains: status.cert_domains,
+        };
+        for ip in status.tailscale_ips {
+            match ip {
+                IpAddr::V4(ip) => state.ipv4 = state.ipv4.or(Some(ip)),
+                IpAddr::V6(ip) => state.ipv6 = state.ipv6.or(Some(ip)),
+            }
         }
+        state
+    }
+}
+
+/// What [`Server::shutdown`] did.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// The number of listeners closed, including packet listeners.
+    pub listeners_closed: usize,
+    /// The number of connections which finished on their own before the timeout.
+    pub connections_drained: usize,
+    /// The number of connections still open at the timeout, which were closed.
+    pub connections_forced: usize,
+    /// Whether the node was ephemeral and logged out of the tailnet.
+    pub logged_out: bool,
+}
+
+/// Options for [`Server::listen_funnel`].
+#[derive(Clone, Debug, Default)]
+pub struct FunnelOptions {
+    /// Only accept connections from the public internet, refusing those from the tailnet.
+    pub funnel_only: bool,
+}
+
+impl FunnelOptions {
+    /// Only accept connections from the public internet, refusing those from the tailnet.
+    pub fn funnel_only(mut self) -> Self {
+        self.funnel_only = true;
+        self
     }
 }
 
@@ -232,11 +965,19 @@ impl Drop for Server {
 #[derive(Default)]
 pub struct ServerBuilder {
     dir: Option<PathBuf>,
+    store: Option<Box<dyn StateStore>>,
+    in_memory_state: bool,
+    allow_state_loss: bool,
+    #[cfg(feature = "encrypted-state")]
+    state_key: Option<Box<dyn FnOnce() -> io::Result<[u8; 32]> + Send>>,
+    #[cfg(feature = "identity")]
+    identity: Option<IdentityBundle>,
     hostname: Option<String>,
     authkey: Option<String>,
     control_url: Option<String>,
     ephemeral: bool,
     log: u8, // 0 = no change, 1 = redirect to `log`, 2 = disable
+    up: Option<Option<Duration>>,
 }
 
 impl ServerBuilder {
@@ -255,6 +996,78 @@ impl ServerBuilder {
         self
     }
 
+    /// Keep the state of the node in `store`, instead of in files under [`ServerBuilder::dir`].
+    ///
+    /// The state directory is still used for logs and other caches.
+    pub fn store(mut self, store: impl StateStore) -> Self {
+        self.store = Some(Box::new(store));
+        self.in_memory_state = false;
+        self
+    }
+
+    /// Keep the state of the node in memory only, so that it is lost once the server is
+    /// closed, instead of in files under [`ServerBuilder::dir`].
+    ///
+    /// The state is never written to disk, but tsnet can't run without a directory, where
+    /// it keeps its log configuration and log buffer. Unless [`ServerBuilder::dir`] is set,
+    /// a private temporary directory is created when the server starts, and removed once it
+    /// is closed or shut down.
+    ///
+    /// As the node can't come back with the same identity, this is meant for
+    /// [ephemeral](ServerBuilder::ephemeral) nodes: building a node that isn't ephemeral
+    /// fails, unless [`ServerBuilder::allow_state_loss`] is set.
+    pub fn in_memory_state(mut self) -> Self {
+        self.in_memory_state = true;
+        self.store = None;
+        self
+    }
+
+    /// Allow [in-memory state](ServerBuilder::in_memory_state) for nodes that aren't
+    /// ephemeral, which leaves a stale node on the tailnet after each run.
+    pub fn allow_state_loss(mut self) -> Self {
+        self.allow_state_loss = true;
+        self
+    }
+
+    /// Encrypt the state of the node with the 256-bit `key`.
+    ///
+    /// This encrypts the [store](ServerBuilder::store) if one is set, and otherwise keeps
+    /// the state in [`ServerBuilder::dir`], which must be set, encrypting a plaintext state
+    /// found there on the first start. Other files in the directory, like logs, are not
+    /// encrypted.
+    ///
+    /// See [`EncryptedStore`]. Requires the `encrypted-state` feature.
+    #[cfg(feature = "encrypted-state")]
+    pub fn encrypt_state(self, key: [u8; 32]) -> Self {
+        self.encrypt_state_with(move || Ok(key))
+    }
+
+    /// Like [`ServerBuilder::encrypt_state`], but getting the key from `provider` when
+    /// building the server, like from a keyring.
+    #[cfg(feature = "encrypted-state")]
+    pub fn encrypt_state_with(
+        mut self,
+        provider: impl FnOnce() -> io::Result<[u8; 32]> + Send + 'static,
+    ) -> Self {
+        self.state_key = Some(Box::new(provider));
+        self
+    }
+
+    /// Give the node the identity exported from another one with
+    /// [`Server::export_identity`], so that it takes over its name, tailnet IPs and ACL
+    /// tags.
+    ///
+    /// The identity replaces the one in the state of the node, which must be kept in
+    /// [`ServerBuilder::dir`] or in a [store](ServerBuilder::store), possibly
+    /// [in memory](ServerBuilder::in_memory_state).
+    ///
+    /// Requires the `identity` feature.
+    #[cfg(feature = "identity")]
+    pub fn import_identity(mut self, identity: IdentityBundle) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
     /// The hostname to present to the control server.
     /// If unset, the binary name is used.
     pub fn hostname(mut self, hostname: &str) -> Self {
@@ -303,12 +1116,65 @@ impl ServerBuilder {
         self
     }
 
+    /// Wait for the node to be usable before returning from [`ServerBuilder::build`].
+    ///
+    /// See [`Server::up`], and [`Server::up_state`] for the state the node came up in.
+    pub fn wait_for_up(mut self) -> Self {
+        self.up = Some(None);
+        self
+    }
+
+    /// Wait up to `timeout` for the node to be usable before returning from
+    /// [`ServerBuilder::build`].
+    ///
+    /// See [`Server::up_with_timeout`].
+    pub fn wait_for_up_timeout(mut self, timeout: Duration) -> Self {
+        self.up = Some(Some(timeout));
+        self
+    }
+
     /// Start the server using the configured options.
     pub fn build(self) -> Result<Server> {
-        let result = unsafe {
-            Server {
-                handle: sys::tailscale_new(),
+        if self.in_memory_state && !self.ephemeral && !self.allow_state_loss {
+            return Err(Error::InvalidConfig(
+                "in-memory state would lose the identity of a node that isn't ephemeral".into(),
+            ));
+        }
+
+        let has_dir = self.dir.is_some();
+        #[cfg(feature = "identity")]
+        if self.identity.is_some() && !has_dir && self.store.is_none() && !self.in_memory_state {
+            return Err(Error::InvalidConfig(
+                "importing an identity needs a state directory or store".into(),
+            ));
+        }
+
+        #[cfg(not(feature = "encrypted-state"))]
+        let store = self.store;
+        #[cfg(feature = "encrypted-state")]
+        let store = match (self.store, self.state_key) {
+            (store, None) => store,
+            (_, Some(_)) if self.in_memory_state => {
+                return Err(Error::InvalidConfig(
+                    "in-memory state can't be encrypted".into(),
+                ))
             }
+            (Some(store), Some(key)) => Some(Box::new(EncryptedStore::new(store, &key()?)) as _),
+            (None, Some(key)) => {
+                let dir = self.dir.as_ref().ok_or_else(|| {
+                    Error::InvalidConfig("encrypting the state needs a state directory".into())
+                })?;
+                Some(Box::new(state::encrypted_dir(dir, &key()?)?) as _)
+            }
+        };
+
+        let handle = unsafe { sys::tailscale_new() };
+        let result = Server {
+            inner: Arc::new(Inner {
+                handle,
+                closed: AtomicBool::new(false),
+                store: store.map(state::Registered::new),
+            }),
         };
 
         match self.log {
@@ -333,9 +1199,9 @@ impl ServerBuilder {
                             }
                         }
                     });
-                unsafe { err(result.handle, sys::tailscale_set_logfd(result.handle, wx))? }
+                check(|error| unsafe { sys::tailscale_set_logfd_err(handle, wx, error) })?;
             }
-            2 => unsafe { err(result.handle, sys::tailscale_set_logfd(result.handle, -1))? },
+            2 => check(|error| unsafe { sys::tailscale_set_logfd_err(handle, -1, error) })?,
             _ => {}
         }
 
@@ -343,52 +1209,62 @@ impl ServerBuilder {
             let dir = dir.into_os_string();
             let dir = dir.into_string().map_err(|_| Error::CantConvertToString)?;
             let dir = CString::new(dir)?;
-            unsafe {
-                err(
-                    result.handle,
-                    sys::tailscale_set_dir(result.handle, dir.as_ptr()),
-                )?
-            }
+            check(|error| unsafe { sys::tailscale_set_dir_err(handle, dir.as_ptr(), error) })?;
+        }
+
+        if let Some(store) = &result.inner.store {
+            check(|error| store.set(handle, error))?;
+        }
+        if self.in_memory_state {
+            check(|error| unsafe { sys::tailscale_set_state_in_memory_err(handle, error) })?;
+        }
+
+        #[cfg(feature = "identity")]
+        let imported = match &self.identity {
+            Some(identity) => identity::import(handle, identity).map(|()| true)?,
+            None => false,
+        };
+        #[cfg(not(feature = "identity"))]
+        let imported = false;
+        if !imported && (has_dir || result.inner.store.is_some()) {
+            identity::check_not_exported(handle)?;
         }
 
         if let Some(hostname) = self.hostname {
             let hostname = CString::new(hostname)?;
-            unsafe {
-                err(
-                    result.handle,
-                    sys::tailscale_set_hostname(result.handle, hostname.as_ptr()),
-                )?
-            }
+            check(|error| unsafe {
+                sys::tailscale_set_hostname_err(handle, hostname.as_ptr(), error)
+            })?;
         }
 
         if let Some(authkey) = self.authkey {
             let authkey = CString::new(authkey)?;
-            unsafe {
-                err(
-                    result.handle,
-                    sys::tailscale_set_authkey(result.handle, authkey.as_ptr()),
-                )?
-            }
+            check(|error| unsafe {
+                sys::tailscale_set_authkey_err(handle, authkey.as_ptr(), error)
+            })?;
         }
 
         if let Some(control_url) = self.control_url {
             let control_url = CString::new(control_url)?;
-            unsafe {
-                err(
-                    result.handle,
-                    sys::tailscale_set_control_url(result.handle, control_url.as_ptr()),
-                )?
-            }
+            check(|error| unsafe {
+                sys::tailscale_set_control_url_err(handle, control_url.as_ptr(), error)
+            })?;
         }
 
-        unsafe {
-            err(
-                result.handle,
-                sys::tailscale_set_ephemeral(result.handle, if self.ephemeral { 1 } else { 0 }),
-            )?
-        }
+        let ephemeral = if self.ephemeral { 1 } else { 0 };
+        check(|error| unsafe { sys::tailscale_set_ephemeral_err(handle, ephemeral, error) })?;
 
-        unsafe { err(result.handle, sys::tailscale_start(result.handle))? }
+        check(|error| unsafe { sys::tailscale_start_err(handle, error) })?;
+
+        match self.up {
+            Some(Some(timeout)) => {
+                result.up_with_timeout(timeout)?;
+            }
+            Some(None) => {
+                result.up()?;
+            }
+            None => {}
+        }
 
         Ok(result)
     }
@@ -403,10 +1279,9 @@ impl ServerBuilder {
 /// ## Examples
 ///
 /// ```rust,no_run
-/// use std::net::TcpStream;
-/// use tsnet::{ServerBuilder, Network, Result};
+/// use tsnet::{Conn, ServerBuilder, Network, Result};
 ///
-/// fn handle_client(stream: TcpStream) {
+/// fn handle_client(conn: Conn) {
 ///     // ...
 /// }
 ///
@@ -422,89 +1297,537 @@ impl ServerBuilder {
 /// }
 /// ```
 pub struct Listener {
-    ts: sys::tailscale,
-    handle: sys::tailscale_listener,
+    ts: Arc<Inner>,
+    handle: OwnedFd,
 }
 
 impl Listener {
     /// Accept a new incoming connection from this listener.
     ///
     /// This function will block the calling thread until a new connection is established.
-    /// When established, the corresponding `TcpStream` will be returned.
-    pub fn accept(&self) -> Result<TcpStream, Error> {
-        unsafe {
-            let mut conn = 0;
-
-            let res = err(
-                self.ts,
-                sys::tailscale_accept(self.handle, &mut conn as *mut _),
-            );
-            res.map(|_| TcpStream::from_raw_fd(conn))
+    /// When established, the corresponding [`Conn`] will be returned.
+    ///
+    /// Note that the stream is local to the process, so its `peer_addr()` is not the address
+    /// of the remote peer. Use [`Listener::accept_incoming`] to know who connected.
+    pub fn accept(&self) -> Result<Conn, Error> {
+        self.accept_incoming().map(IncomingConn::into_conn)
+    }
+
+    /// Accept a new incoming connection from this listener, along with its remote address.
+    ///
+    /// This function will block the calling thread until a new connection is established.
+    ///
+    /// Returns [`Error::ServerClosed`] once the server of this listener has been closed.
+    pub fn accept_incoming(&self) -> Result<IncomingConn, Error> {
+        let conn = accept::recv_conn(self.handle.as_fd())?.ok_or(Error::ServerClosed)?;
+        Ok(IncomingConn::new(&self.ts, conn, |fd| {
+            Ok(TcpStream::from(fd))
+        })?)
+    }
+}
+
+/// A connection on the tailnet, as made by [`Server::connect`] or accepted by
+/// [`Listener::accept`].
+///
+/// The connection keeps its node alive, unlike its stream once taken out with
+/// [`Conn::into_stream`]. It reads and writes through the stream, and dereferences to it.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use std::io::Write;
+/// use tsnet::{ServerBuilder, Network};
+///
+/// let mut conn = ServerBuilder::new()
+///     .ephemeral()
+///     .build()
+///     .unwrap()
+///     .connect(Network::Tcp, "echo-server:1999")
+///     .unwrap();
+/// // The server handle is gone, but the node is still up.
+/// writeln!(conn, "hello from {}", conn.local_addr().unwrap()).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Conn<S = TcpStream> {
+    stream: S,
+    ts: Arc<Inner>,
+}
+
+impl<S> Conn<S> {
+    fn new(ts: &Arc<Inner>, stream: S) -> Self {
+        Conn {
+            stream,
+            ts: ts.clone(),
         }
     }
+
+    /// Take the stream out of the connection, after which it doesn't keep the node alive.
+    pub fn into_stream(self) -> S {
+        self.stream
+    }
 }
 
-impl Drop for Listener {
-    fn drop(&mut self) {
-        unsafe {
-            sys::tailscale_close(self.handle);
+impl Conn {
+    /// Create a new independently owned handle to the same connection.
+    pub fn try_clone(&self) -> io::Result<Conn> {
+        Ok(Conn {
+            stream: self.stream.try_clone()?,
+            ts: self.ts.clone(),
+        })
+    }
+}
+
+impl<S> Deref for Conn<S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        &self.stream
+    }
+}
+
+impl<S> DerefMut for Conn<S> {
+    fn deref_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+}
+
+impl<S: Read> Read for Conn<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl<S: Write> Write for Conn<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+impl<'a, S> Read for &'a Conn<S>
+where
+    &'a S: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut stream: &'a S = &self.stream;
+        stream.read(buf)
+    }
+}
+
+impl<'a, S> Write for &'a Conn<S>
+where
+    &'a S: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut stream: &'a S = &self.stream;
+        stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let mut stream: &'a S = &self.stream;
+        stream.flush()
+    }
+}
+
+impl<S: AsFd> AsFd for Conn<S> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.stream.as_fd()
+    }
+}
+
+impl<S: AsRawFd> AsRawFd for Conn<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+/// A connection accepted by a [`Listener`], along with where it came from.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use tsnet::{ServerBuilder, Network};
+///
+/// let ts = ServerBuilder::new().ephemeral().build().unwrap();
+/// let ln = ts.listen(Network::Tcp, ":1999").unwrap();
+///
+/// let conn = ln.accept_incoming().unwrap();
+/// let who = conn.identity().unwrap();
+/// println!("{} connected from {}", who.user_profile.login_name, conn.remote_addr);
+/// ```
+#[derive(Debug)]
+pub struct IncomingConn<S = TcpStream> {
+    /// The connection.
+    ///
+    /// Once moved out, it doesn't keep the node alive, unlike [`IncomingConn::into_conn`].
+    pub stream: S,
+    /// The tailnet address of the remote peer.
+    ///
+    /// For connections [from Funnel](IncomingConn::via_funnel), this is the address of the
+    /// client on the public internet.
+    pub remote_addr: SocketAddr,
+    /// Whether the connection arrived from the public internet via Funnel, rather than from
+    /// inside the tailnet.
+    ///
+    /// See [`Server::listen_funnel`].
+    pub via_funnel: bool,
+    ts: Arc<Inner>,
+}
+
+impl<S> IncomingConn<S> {
+    /// Wrap a received connection, making a stream of type `S` out of it with `stream`.
+    fn new(
+        ts: &Arc<Inner>,
+        conn: accept::Accepted,
+        stream: impl FnOnce(OwnedFd) -> io::Result<S>,
+    ) -> io::Result<Self> {
+        Ok(IncomingConn {
+            stream: stream(conn.fd)?,
+            remote_addr: conn.remote_addr,
+            via_funnel: conn.via_funnel,
+            ts: ts.clone(),
+        })
+    }
+
+    /// Look up the node and user on the other end of the connection.
+    ///
+    /// Connections [from Funnel](IncomingConn::via_funnel) have no tailnet identity, so this
+    /// fails for them.
+    ///
+    /// See [`Server::whois`].
+    pub fn identity(&self) -> Result<local::WhoIs> {
+        whois(self.ts.handle()?, &self.remote_addr.to_string())
+    }
+
+    /// The connection, without where it came from.
+    pub fn into_conn(self) -> Conn<S> {
+        Conn {
+            stream: self.stream,
+            ts: self.ts,
+        }
+    }
+
+    /// Where the connection came from, detached from the stream.
+    pub fn peer(&self) -> PeerAddr {
+        PeerAddr {
+            remote_addr: self.remote_addr,
+            via_funnel: self.via_funnel,
+            ts: self.ts.clone(),
         }
     }
 }
 
+/// The peer of an accepted connection, as returned by [`IncomingConn::peer`].
+///
+/// This is what integrations with servers that take the stream and the address of a
+/// connection separately attach to each connection.
+#[derive(Clone, Debug)]
+pub struct PeerAddr {
+    /// The address of the peer.
+    ///
+    /// See [`IncomingConn::remote_addr`].
+    pub remote_addr: SocketAddr,
+    /// Whether the connection came in from the public internet via Funnel.
+    pub via_funnel: bool,
+    ts: Arc<Inner>,
+}
+
+impl PeerAddr {
+    /// Look up the node and user of the peer.
+    ///
+    /// This blocks on the LocalAPI, so in async code call it from a blocking task.
+    ///
+    /// See [`IncomingConn::identity`].
+    pub fn identity(&self) -> Result<local::WhoIs> {
+        whois(self.ts.handle()?, &self.remote_addr.to_string())
+    }
+}
+
+/// Iterates over accepted connections, ending once the server has been closed.
 impl Iterator for Listener {
-    type Item = Result<TcpStream>;
-    fn next(&mut self) -> Option<Result<TcpStream>> {
-        Some(self.accept())
+    type Item = Result<Conn>;
+    fn next(&mut self) -> Option<Result<Conn>> {
+        (&*self).next()
     }
 }
 
+/// Iterates over accepted connections, ending once the server has been closed.
 impl Iterator for &Listener {
-    type Item = Result<TcpStream>;
-    fn next(&mut self) -> Option<Result<TcpStream>> {
-        Some(self.accept())
+    type Item = Result<Conn>;
+    fn next(&mut self) -> Option<Result<Conn>> {
+        match self.accept() {
+            Err(Error::ServerClosed) => None,
+            res => Some(res),
+        }
     }
 }
 
+/// Make a socketpair handle from libtailscale non-blocking, and register it with tokio.
 #[cfg(feature = "tokio")]
+fn async_fd(fd: OwnedFd) -> io::Result<AsyncFd<OwnedFd>> {
+    // Any socket will do to reach the fcntl.
+    let socket = UnixStream::from(fd);
+    socket.set_nonblocking(true)?;
+    // Safety: the fd is owned, so it stays open for as long as the AsyncFd.
+    Ok(unsafe { AsyncFd::register(OwnedFd::from(socket)) }?)
+}
+
+/// Read the result of a `tailscale_op` which is done: the connection it made, if any.
+///
+/// Fails with [`io::ErrorKind::WouldBlock`] while the operation is still running.
+#[cfg(any(feature = "tokio", feature = "async-io"))]
+fn op_result(op: BorrowedFd<'_>) -> io::Result<Result<Option<OwnedFd>>> {
+    let mut conn = -1;
+    let mut code = 0;
+    let mut msg = vec![0 as c_char; sys::TAILSCALE_OP_ERRMSG_LEN as usize];
+
+    let res = unsafe {
+        sys::tailscale_op_result(
+            op.as_raw_fd(),
+            &mut conn,
+            &mut code,
+            msg.as_mut_ptr(),
+            msg.len(),
+        )
+    };
+    match res {
+        0 if conn == -1 => Ok(Ok(None)),
+        0 => Ok(Ok(Some(unsafe { OwnedFd::from_raw_fd(conn) }))),
+        -1 => {
+            let msg = unsafe { CStr::from_ptr(msg.as_ptr()) };
+            Ok(Err(
+                match error_from_code(code, msg.to_string_lossy().into_owned()) {
+                    // The server was closed while the operation ran.
+                    Error::UnknownHandle => Error::ServerClosed,
+                    e => e,
+                },
+            ))
+        }
+        errno => Err(io::Error::from_raw_os_error(errno)),
+    }
+}
+
+/// A [`Listener`] for use with tokio, as created by [`Server::listen_async`].
+///
+/// Accepting is driven by the tokio reactor, without blocking any threads.
+/// The listener is also a [`Stream`] of connections, and can be served with
+/// `tsnet::http::serve` with the `http` feature, or with `axum::serve` with the `axum` feature.
+/// Requires the `tokio` feature.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use tsnet::{ServerBuilder, Network};
+///
+/// # async fn run() -> tsnet::Result<()> {
+/// let ts = ServerBuilder::new().ephemeral().build()?;
+/// let ln = ts.listen_async(Network::Tcp, ":1999")?;
+///
+/// loop {
+///     let conn = ln.accept_incoming().await?;
+///     println!("connection from {}", conn.remote_addr);
+/// }
+/// # }
+/// ```
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
 pub struct AsyncListener {
-    listener: Listener,
-    fut: Option<task::JoinHandle<Result<net::TcpStream>>>,
+    ts: Arc<Inner>,
+    handle: AsyncFd<OwnedFd>,
 }
 
 #[cfg(feature = "tokio")]
-impl Accept for AsyncListener {
-    type Conn = net::TcpStream;
-    type Error = Error;
+impl AsyncListener {
+    /// Make a listener async, registering it with the tokio reactor.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn new(listener: Listener) -> Result<Self> {
+        let Listener { ts, handle } = listener;
+        Ok(AsyncListener {
+            ts,
+            handle: async_fd(handle)?,
+        })
+    }
 
-    fn poll_accept(
+    /// Accept a new incoming connection from this listener.
+    ///
+    /// Returns [`Error::ServerClosed`] once the server of this listener has been closed.
+    pub async fn accept(&self) -> Result<Conn<net::TcpStream>> {
+        self.accept_incoming().await.map(IncomingConn::into_conn)
+    }
+
+    /// Accept a new incoming connection from this listener, along with its remote address.
+    ///
+    /// Returns [`Error::ServerClosed`] once the server of this listener has been closed.
+    pub async fn accept_incoming(&self) -> Result<IncomingConn<net::TcpStream>> {
+        poll_fn(|cx| self.poll_accept(cx)).await
+    }
+
+    /// Poll for a new incoming connection, registering the current task for wakeup once one
+    /// is pending.
+    pub fn poll_accept(&self, cx: &mut Context<'_>) -> Poll<Result<IncomingConn<net::TcpStream>>> {
+        loop {
+            let mut ready = match self.handle.poll_read_ready(cx) {
+                Poll::Ready(ready) => ready?,
+                Poll::Pending => return Poll::Pending,
+            };
+            let conn = match ready.try_io(|fd| accept::recv_conn(fd.get_ref().as_fd())) {
+                Ok(conn) => conn?.ok_or(Error::ServerClosed)?,
+                Err(_would_block) => continue,
+            };
+
+            let conn = IncomingConn::new(&self.ts, conn, |fd| {
+                let stream = TcpStream::from(fd);
+                stream.set_nonblocking(true)?;
+                net::TcpStream::from_std(stream)
+            })?;
+            return Poll::Ready(Ok(conn));
+        }
+    }
+}
+
+/// Streams accepted connections, ending once the server has been closed.
+#[cfg(feature = "tokio")]
+impl Stream for AsyncListener {
+    type Item = io::Result<Conn<net::TcpStream>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.poll_accept(cx) {
+            Poll::Ready(Ok(conn)) => Poll::Ready(Some(Ok(conn.into_conn()))),
+            Poll::Ready(Err(Error::ServerClosed)) => Poll::Ready(None),
+            Poll::Ready(Err(err)) => Poll::Ready(Some(Err(err.into()))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Reads from the stream of the connection.
+#[cfg(feature = "tokio")]
+impl<S: AsyncRead + Unpin> AsyncRead for IncomingConn<S> {
+    fn poll_read(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
-    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
-        if self.fut.is_none() {
-            let ts = self.listener.handle;
-            self.fut = Some(task::spawn_blocking(move || unsafe {
-                let mut conn = 0;
-
-                let res = err(ts, sys::tailscale_accept(ts, &mut conn as *mut _));
-                res.map(|_| {
-                    let stream = TcpStream::from_raw_fd(conn);
-                    let _ = stream.set_nonblocking(true);
-                    net::TcpStream::from_std(stream).unwrap()
-                })
-            }));
-        }
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+/// Writes to the stream of the connection.
+#[cfg(feature = "tokio")]
+impl<S: AsyncWrite + Unpin> AsyncWrite for IncomingConn<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
 
-        let mut fut = self.fut.take().unwrap();
-        let fut_pl = Pin::new(&mut fut);
-        let stream = match fut_pl.poll(cx) {
-            Poll::Ready(t) => t,
-            Poll::Pending => {
-                self.fut = Some(fut);
-                return Poll::Pending;
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+/// Reads from the stream of the connection.
+#[cfg(feature = "tokio")]
+impl<S: AsyncRead + Unpin> AsyncRead for Conn<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+/// Writes to the stream of the connection.
+#[cfg(feature = "tokio")]
+impl<S: AsyncWrite + Unpin> AsyncWrite for Conn<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn up_state_from_status() {
+        let status: local::Status = serde_json::from_str(
+            r#"{
+                "BackendState": "Running",
+                "TailscaleIPs": ["fd7a:115c:a1e0::1", "100.64.0.1", "100.64.0.2"],
+                "Self": {
+                    "HostName": "web",
+                    "DNSName": "web.tail1234.ts.net.",
+                    "Tags": ["tag:server"]
+                },
+                "MagicDNSSuffix": "tail1234.ts.net",
+                "CertDomains": ["web.tail1234.ts.net"]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            UpState::from(status),
+            UpState {
+                ipv4: Some(Ipv4Addr::new(100, 64, 0, 1)),
+                ipv6: Some("fd7a:115c:a1e0::1".parse().unwrap()),
+                hostname: "web".into(),
+                dns_name: "web.tail1234.ts.net.".into(),
+                magic_dns_suffix: "tail1234.ts.net".into(),
+                tags: vec!["tag:server".into()],
+                cert_domains: vec!["web.tail1234.ts.net".into()],
             }
-        };
+        );
+    }
+
+    #[test]
+    fn conn_keeps_the_node_alive() {
+        // Already closed, so that dropping it doesn't call into the server.
+        let ts = Arc::new(Inner {
+            handle: -1,
+            closed: true.into(),
+            store: None,
+        });
+        let ln = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(ln.local_addr().unwrap()).unwrap();
+
+        let conn = Conn::new(&ts, stream);
+        let clone = conn.try_clone().unwrap();
+        assert_eq!(Arc::strong_count(&ts), 3);
+        drop(clone);
+        let stream = conn.into_stream();
+        assert_eq!(Arc::strong_count(&ts), 1);
+        drop(stream);
+    }
 
-        Poll::Ready(Some(stream.unwrap()))
+    #[test]
+    fn up_state_before_login() {
+        let status: local::Status =
+            serde_json::from_str(r#"{"TailscaleIPs": null, "Self": null, "CertDomains": null}"#)
+                .unwrap();
+        assert_eq!(UpState::from(status), UpState::default());
     }
 }
@@ -0,0 +1,580 @@
+//! A client for the LocalAPI of an embedded node.
+//!
+//! The LocalAPI is what the `tailscale` CLI uses to talk to `tailscaled`.
+//! With [`LocalClient`] a program can introspect its own node the same way:
+//! query its status, its peers, its preferences, and identify who is on the other end
+//! of a connection.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use tsnet::ServerBuilder;
+//!
+//! let server = ServerBuilder::new().ephemeral().wait_for_up().build().unwrap();
+//! let client = server.local_client().unwrap();
+//!
+//! let status = client.status().unwrap();
+//! for peer in status.peer.values() {
+//!     println!("{} online={}", peer.dns_name, peer.online);
+//! }
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    io::{BufRead, BufReader, Read, Write},
+    net::{IpAddr, TcpStream},
+};
+
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{Error, Loopback, Result};
+
+/// A client for the LocalAPI, talking to the node over its [`Loopback`] server.
+///
+/// Get one from [`Server::local_client`](crate::Server::local_client).
+#[derive(Clone, Debug)]
+pub struct LocalClient {
+    loopback: Loopback,
+}
+
+impl LocalClient {
+    /// Create a client using the given loopback server.
+    pub fn new(loopback: Loopback) -> Self {
+        LocalClient { loopback }
+    }
+
+    /// The status of the node and its peers.
+    pub fn status(&self) -> Result<Status> {
+        self.get_json("status")
+    }
+
+    /// Look up the node and user owning the given tailnet address.
+    ///
+    /// `addr` can be an IP, or an IP and port, like the remote address of a connection.
+    pub fn whois(&self, addr: &str) -> Result<WhoIs> {
+        self.get_json(&format!("whois?addr={}", query_escape(addr)))
+    }
+
+    /// The current preferences of the node.
+    pub fn prefs(&self) -> Result<Prefs> {
+        self.get_json("prefs")
+    }
+
+    /// The current network map of the node: itself, its peers and their owners.
+    pub fn netmap(&self) -> Result<NetMap> {
+        // Subscribe to the IPN bus, asking for the current netmap without private keys,
+        // and hang up once we got it.
+        const NOTIFY_INITIAL_NETMAP: u64 = 1 << 3;
+        const NOTIFY_NO_PRIVATE_KEYS: u64 = 1 << 4;
+        let path = format!(
+            "watch-ipn-bus?mask={}",
+            NOTIFY_INITIAL_NETMAP | NOTIFY_NO_PRIVATE_KEYS
+        );
+
+        let mut body = self.request("GET", &path)?;
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if body.read_line(&mut line)? == 0 {
+                return Err(Error::TSNet(
+                    "localapi: IPN bus closed before sending a netmap".to_string(),
+                ));
+            }
+
+            let notify: Notify = serde_json::from_str(&line)?;
+            if let Some(msg) = notify.err_message {
+                return Err(Error::TSNet(msg));
+            }
+            if let Some(netmap) = notify.net_map {
+                return Ok(netmap);
+            }
+        }
+    }
+
+    /// Ping the given tailnet IP, using the specified kind of ping.
+    pub fn ping(&self, ip: IpAddr, kind: PingType) -> Result<PingResult> {
+        let path = format!("ping?ip={}&type={kind}", query_escape(&ip.to_string()));
+        let body = self.request("POST", &path)?;
+        Ok(serde_json::from_reader(body)?)
+    }
+
+    fn get_json<T: DeserializeOwned>(&self, path: &str) -> Result<T> {
+        let body = self.request("GET", path)?;
+        Ok(serde_json::from_reader(body)?)
+    }
+
+    /// Send a request to the LocalAPI, returning the body of a successful response.
+    ///
+    /// This speaks HTTP/1.0 so that the server delimits the body by closing the connection.
+    fn request(&self, method: &str, path: &str) -> Result<BufReader<TcpStream>> {
+        let mut stream = TcpStream::connect(self.loopback.addr)?;
+
+        let mut req = format!(
+            "{method} /localapi/v0/{path} HTTP/1.0\r\nHost: {}\r\nContent-Length: 0\r\n",
+            self.loopback.addr
+        );
+        for (name, value) in self.loopback.local_api_headers() {
+            req.push_str(&format!("{name}: {value}\r\n"));
+        }
+        req.push_str("\r\n");
+        stream.write_all(req.as_bytes())?;
+
+        let mut body = BufReader::new(stream);
+        let mut line = String::new();
+        body.read_line(&mut line)?;
+        let status = line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| Error::TSNet(format!("localapi: malformed response: {line:?}")))?;
+
+        // Skip over the headers.
+        loop {
+            line.clear();
+            if body.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                break;
+            }
+        }
+
+        if status != 200 {
+            let mut message = String::new();
+            body.read_to_string(&mut message)?;
+            return Err(Error::LocalApi {
+                status,
+                message: message.trim().to_string(),
+            });
+        }
+
+        Ok(body)
+    }
+}
+
+/// Percent-encode a value for use in a URL query.
+fn query_escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(b as char)
+            }
+            _ => out.push_str(&format!("%{b:02X}")),
+        }
+    }
+    out
+}
+
+/// The kind of ping to send with [`LocalClient::ping`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PingType {
+    /// Ping the peer's WireGuard endpoint, without going through WireGuard.
+    Disco,
+    /// Ping through WireGuard, answered by the peer's Tailscale stack.
+    Tsmp,
+    /// An ICMP echo through WireGuard, answered by the peer's network stack.
+    Icmp,
+    /// An HTTP request to the peer's PeerAPI.
+    PeerApi,
+}
+
+impl Display for PingType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PingType::Disco => write!(f, "disco"),
+            PingType::Tsmp => write!(f, "TSMP"),
+            PingType::Icmp => write!(f, "ICMP"),
+            PingType::PeerApi => write!(f, "peerapi"),
+        }
+    }
+}
+
+/// The status of a node and its peers, as returned by [`LocalClient::status`].
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Status {
+    /// The version of Tailscale running the node.
+    #[serde(rename = "Version")]
+    pub version: String,
+    /// The state of the node, like `Running`, `NeedsLogin` or `Starting`.
+    #[serde(rename = "BackendState")]
+    pub backend_state: String,
+    /// The URL to visit to log in, if the node needs to.
+    #[serde(rename = "AuthURL")]
+    pub auth_url: String,
+    /// The tailnet addresses of the node.
+    #[serde(rename = "TailscaleIPs", deserialize_with = "null_as_default")]
+    pub tailscale_ips: Vec<IpAddr>,
+    /// The node itself.
+    #[serde(rename = "Self")]
+    pub self_status: Option<PeerStatus>,
+    /// Health problems the node is aware of.
+    #[serde(rename = "Health", deserialize_with = "null_as_default")]
+    pub health: Vec<String>,
+    /// The MagicDNS suffix of the tailnet, like `tail1234.ts.net`.
+    #[serde(rename = "MagicDNSSuffix")]
+    pub magic_dns_suffix: String,
+    /// The names the node can obtain TLS certificates for.
+    #[serde(rename = "CertDomains", deserialize_with = "null_as_default")]
+    pub cert_domains: Vec<String>,
+    /// The peers of the node, keyed by their public node key.
+    #[serde(rename = "Peer", deserialize_with = "null_as_default")]
+    pub peer: HashMap<String, PeerStatus>,
+    /// The owners of the node and its peers, keyed by user ID.
+    #[serde(rename = "User", deserialize_with = "null_as_default")]
+    pub user: HashMap<String, UserProfile>,
+}
+
+/// The status of a single node, as part of [`Status`].
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct PeerStatus {
+    /// The stable ID of the node.
+    #[serde(rename = "ID")]
+    pub id: String,
+    /// The public node key.
+    #[serde(rename = "PublicKey")]
+    pub public_key: String,
+    /// The hostname the node reported.
+    #[serde(rename = "HostName")]
+    pub host_name: String,
+    /// The MagicDNS name of the node, with a trailing dot.
+    #[serde(rename = "DNSName")]
+    pub dns_name: String,
+    /// The operating system of the node.
+    #[serde(rename = "OS")]
+    pub os: String,
+    /// The ID of the user owning the node.
+    #[serde(rename = "UserID")]
+    pub user_id: i64,
+    /// The tailnet addresses of the node.
+    #[serde(rename = "TailscaleIPs", deserialize_with = "null_as_default")]
+    pub tailscale_ips: Vec<IpAddr>,
+    /// The ACL tags of the node.
+    #[serde(rename = "Tags", deserialize_with = "null_as_default")]
+    pub tags: Vec<String>,
+    /// The current direct address of the node, if connected directly.
+    #[serde(rename = "CurAddr")]
+    pub cur_addr: String,
+    /// The DERP region the node is reachable through.
+    #[serde(rename = "Relay")]
+    pub relay: String,
+    /// Bytes received from the node.
+    #[serde(rename = "RxBytes")]
+    pub rx_bytes: i64,
+    /// Bytes sent to the node.
+    #[serde(rename = "TxBytes")]
+    pub tx_bytes: i64,
+    /// When the node was last seen by the control server, in RFC 3339 format.
+    #[serde(rename = "LastSeen")]
+    pub last_seen: String,
+    /// Whether the node is connected to the control server.
+    #[serde(rename = "Online")]
+    pub online: bool,
+    /// Whether the node is currently used as an exit node.
+    #[serde(rename = "ExitNode")]
+    pub exit_node: bool,
+    /// Whether the node can be used as an exit node.
+    #[serde(rename = "ExitNodeOption")]
+    pub exit_node_option: bool,
+    /// Whether there was recent traffic with the node.
+    #[serde(rename = "Active")]
+    pub active: bool,
+}
+
+/// The owner of a node and the node itself, as returned by [`LocalClient::whois`].
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct WhoIs {
+    /// The node.
+    #[serde(rename = "Node")]
+    pub node: Node,
+    /// The user owning the node.
+    ///
+    /// For tagged nodes, this is a placeholder user.
+    #[serde(rename = "UserProfile")]
+    pub user_profile: UserProfile,
+    /// The capabilities granted to the node by the tailnet policy.
+    #[serde(rename = "CapMap", deserialize_with = "null_as_default")]
+    pub cap_map: HashMap<String, Vec<serde_json::Value>>,
+}
+
+/// A node of the tailnet, as seen by the control server.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Node {
+    /// The numeric ID of the node.
+    #[serde(rename = "ID")]
+    pub id: i64,
+    /// The stable ID of the node.
+    #[serde(rename = "StableID")]
+    pub stable_id: String,
+    /// The MagicDNS name of the node, with a trailing dot.
+    #[serde(rename = "Name")]
+    pub name: String,
+    /// The ID of the user owning the node.
+    #[serde(rename = "User")]
+    pub user: i64,
+    /// The public node key.
+    #[serde(rename = "Key")]
+    pub key: String,
+    /// The tailnet addresses of the node, as prefixes like `100.64.0.1/32`.
+    #[serde(rename = "Addresses", deserialize_with = "null_as_default")]
+    pub addresses: Vec<String>,
+    /// The ACL tags of the node.
+    #[serde(rename = "Tags", deserialize_with = "null_as_default")]
+    pub tags: Vec<String>,
+    /// Whether the node is connected to the control server, if known.
+    #[serde(rename = "Online")]
+    pub online: Option<bool>,
+    /// The name of the node as computed from its hostname.
+    #[serde(rename = "ComputedName")]
+    pub computed_name: String,
+    /// The capabilities of the node.
+    #[serde(rename = "Capabilities", deserialize_with = "null_as_default")]
+    pub capabilities: Vec<String>,
+}
+
+/// A user of the tailnet.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct UserProfile {
+    /// The numeric ID of the user.
+    #[serde(rename = "ID")]
+    pub id: i64,
+    /// The login name of the user, like `alice@example.com`.
+    #[serde(rename = "LoginName")]
+    pub login_name: String,
+    /// The display name of the user.
+    #[serde(rename = "DisplayName")]
+    pub display_name: String,
+    /// The URL of the user's profile picture.
+    #[serde(rename = "ProfilePicURL")]
+    pub profile_pic_url: String,
+}
+
+/// The preferences of a node, as returned by [`LocalClient::prefs`].
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Prefs {
+    /// The coordination server URL.
+    #[serde(rename = "ControlURL")]
+    pub control_url: String,
+    /// Whether to accept subnet routes advertised by other nodes.
+    #[serde(rename = "RouteAll")]
+    pub route_all: bool,
+    /// The stable ID of the exit node in use, if any.
+    #[serde(rename = "ExitNodeID")]
+    pub exit_node_id: String,
+    /// Whether to use the DNS configuration of the tailnet.
+    #[serde(rename = "CorpDNS")]
+    pub corp_dns: bool,
+    /// Whether the node wants to be connected.
+    #[serde(rename = "WantRunning")]
+    pub want_running: bool,
+    /// Whether the node was explicitly logged out.
+    #[serde(rename = "LoggedOut")]
+    pub logged_out: bool,
+    /// Whether incoming connections are blocked.
+    #[serde(rename = "ShieldsUp")]
+    pub shields_up: bool,
+    /// The ACL tags the node requests.
+    #[serde(rename = "AdvertiseTags", deserialize_with = "null_as_default")]
+    pub advertise_tags: Vec<String>,
+    /// The hostname the node presents.
+    #[serde(rename = "Hostname")]
+    pub hostname: String,
+    /// The subnet routes the node advertises.
+    #[serde(rename = "AdvertiseRoutes", deserialize_with = "null_as_default")]
+    pub advertise_routes: Vec<String>,
+}
+
+/// The network map of a node, as returned by [`LocalClient::netmap`].
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct NetMap {
+    /// The node itself.
+    #[serde(rename = "SelfNode")]
+    pub self_node: Option<Node>,
+    /// The MagicDNS name of the node, with a trailing dot.
+    #[serde(rename = "Name")]
+    pub name: String,
+    /// The tailnet addresses of the node, as prefixes.
+    #[serde(rename = "Addresses", deserialize_with = "null_as_default")]
+    pub addresses: Vec<String>,
+    /// When the node key expires, in RFC 3339 format.
+    #[serde(rename = "Expiry")]
+    pub expiry: String,
+    /// The peers visible to the node.
+    #[serde(rename = "Peers", deserialize_with = "null_as_default")]
+    pub peers: Vec<Node>,
+    /// The name of the tailnet.
+    #[serde(rename = "Domain")]
+    pub domain: String,
+    /// The owners of the node and its peers, keyed by user ID.
+    #[serde(rename = "UserProfiles", deserialize_with = "null_as_default")]
+    pub user_profiles: HashMap<String, UserProfile>,
+}
+
+/// The outcome of [`LocalClient::ping`].
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct PingResult {
+    /// The IP that was pinged.
+    #[serde(rename = "IP")]
+    pub ip: String,
+    /// The tailnet IP of the node that answered.
+    #[serde(rename = "NodeIP")]
+    pub node_ip: String,
+    /// The name of the node that answered.
+    #[serde(rename = "NodeName")]
+    pub node_name: String,
+    /// Why the ping failed, if it did.
+    #[serde(rename = "Err")]
+    pub err: String,
+    /// The round trip time, in seconds.
+    #[serde(rename = "LatencySeconds")]
+    pub latency_seconds: f64,
+    /// The direct endpoint of the peer, if the ping went direct.
+    #[serde(rename = "Endpoint")]
+    pub endpoint: String,
+    /// The DERP region the ping went through, if it was relayed.
+    #[serde(rename = "DERPRegionID")]
+    pub derp_region_id: i64,
+    /// The code of the DERP region the ping went through, if it was relayed.
+    #[serde(rename = "DERPRegionCode")]
+    pub derp_region_code: String,
+}
+
+/// A message from the IPN bus.
+#[derive(Deserialize)]
+struct Notify {
+    #[serde(rename = "ErrMessage")]
+    err_message: Option<String>,
+    #[serde(rename = "NetMap")]
+    net_map: Option<NetMap>,
+}
+
+/// Go marshals nil slices and maps as `null`.
+fn null_as_default<'de, D, T>(de: D) -> Result<T, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Default + Deserialize<'de>,
+{
+    Ok(Option::<T>::deserialize(de)?.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_escape_reserved() {
+        assert_eq!(query_escape("100.64.0.1:80"), "100.64.0.1%3A80");
+        assert_eq!(query_escape("[fd7a::1]:443"), "%5Bfd7a%3A%3A1%5D%3A443");
+        assert_eq!(
+            query_escape("a b&c=d/e?f#g+h%"),
+            "a%20b%26c%3Dd%2Fe%3Ff%23g%2Bh%25"
+        );
+        assert_eq!(query_escape("unreserved-_.~AZaz09"), "unreserved-_.~AZaz09");
+        assert_eq!(query_escape("é"), "%C3%A9");
+        assert_eq!(query_escape(""), "");
+    }
+
+    #[test]
+    fn parse_status() {
+        let status: Status =
+            serde_json::from_str(include_str!("testdata/localapi/status.json")).unwrap();
+        assert_eq!(status.version, "1.48.0-t1234abcd");
+        assert_eq!(status.backend_state, "Running");
+        assert_eq!(
+            status.tailscale_ips,
+            [
+                "100.101.102.103".parse::<IpAddr>().unwrap(),
+                "fd7a:115c:a1e0:ab12:4843:cd96:6265:6667".parse().unwrap(),
+            ]
+        );
+        assert!(status.health.is_empty());
+        assert_eq!(status.cert_domains, ["web.tail1234.ts.net"]);
+
+        let me = status.self_status.unwrap();
+        assert_eq!(me.dns_name, "web.tail1234.ts.net.");
+        assert_eq!(me.tags, ["tag:server"]);
+        assert!(me.online);
+
+        let peer = status.peer.values().next().unwrap();
+        assert_eq!(peer.host_name, "laptop");
+        assert!(peer.tags.is_empty());
+        assert_eq!(peer.rx_bytes, 1024);
+        assert!(peer.exit_node_option);
+        assert_eq!(status.user["12345"].login_name, "alice@example.com");
+    }
+
+    #[test]
+    fn parse_whois() {
+        let who: WhoIs =
+            serde_json::from_str(include_str!("testdata/localapi/whois.json")).unwrap();
+        assert_eq!(who.node.id, 1234567890);
+        assert_eq!(who.node.name, "laptop.tail1234.ts.net.");
+        assert_eq!(who.node.addresses[0], "100.90.80.70/32");
+        assert!(who.node.tags.is_empty());
+        assert_eq!(who.node.online, Some(true));
+        assert_eq!(who.user_profile.display_name, "Alice");
+        assert_eq!(who.cap_map["example.com/cap/admin"].len(), 1);
+    }
+
+    #[test]
+    fn parse_prefs() {
+        let prefs: Prefs =
+            serde_json::from_str(include_str!("testdata/localapi/prefs.json")).unwrap();
+        assert_eq!(prefs.control_url, "https://controlplane.tailscale.com");
+        assert!(prefs.corp_dns && prefs.want_running);
+        assert!(!prefs.logged_out);
+        assert_eq!(prefs.advertise_tags, ["tag:server"]);
+        assert!(prefs.advertise_routes.is_empty());
+        assert_eq!(prefs.hostname, "web");
+    }
+
+    #[test]
+    fn parse_netmap_from_ipn_bus() {
+        let mut lines = include_str!("testdata/localapi/watch-ipn-bus.jsonl").lines();
+
+        let first: Notify = serde_json::from_str(lines.next().unwrap()).unwrap();
+        assert!(first.net_map.is_none() && first.err_message.is_none());
+
+        let notify: Notify = serde_json::from_str(lines.next().unwrap()).unwrap();
+        let netmap = notify.net_map.unwrap();
+        assert_eq!(netmap.name, "web.tail1234.ts.net.");
+        assert_eq!(netmap.self_node.unwrap().online, None);
+        assert_eq!(netmap.peers.len(), 1);
+        assert_eq!(netmap.peers[0].computed_name, "laptop");
+        assert_eq!(netmap.domain, "example.com");
+        assert_eq!(
+            netmap.user_profiles["12345"].login_name,
+            "alice@example.com"
+        );
+    }
+
+    #[test]
+    fn parse_ping() {
+        let ping: PingResult =
+            serde_json::from_str(include_str!("testdata/localapi/ping.json")).unwrap();
+        assert_eq!(ping.node_name, "laptop");
+        assert!(ping.err.is_empty());
+        assert!((ping.latency_seconds - 0.012345).abs() < 1e-9);
+        assert_eq!(ping.endpoint, "192.0.2.10:41641");
+    }
+
+    #[test]
+    fn ping_type_names() {
+        let names: Vec<_> = [
+            PingType::Disco,
+            PingType::Tsmp,
+            PingType::Icmp,
+            PingType::PeerApi,
+        ]
+        .iter()
+        .map(ToString::to_string)
+        .collect();
+        assert_eq!(names, ["disco", "TSMP", "ICMP", "peerapi"]);
+    }
+}
@@ -0,0 +1,73 @@
+//! The loopback server: a SOCKS5 proxy onto the tailnet, and the LocalAPI.
+
+use std::{fmt, net::SocketAddr};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+/// A credential, which doesn't reveal its value when printed.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub(crate) fn new(secret: String) -> Self {
+        Secret(secret)
+    }
+
+    /// Get at the actual value of the credential.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+/// A loopback address server, as started by [`Server::loopback`](crate::Server::loopback).
+///
+/// The server listens on a local address and has multiple functions:
+///
+/// * It is a SOCKS5 proxy onto the tailnet, authenticated with the username `tsnet` and
+///   [`Loopback::proxy_cred`] as the password. See [`Loopback::proxy_url`].
+/// * It serves the "LocalAPI" over HTTP on `/localapi`. As the LocalAPI is powerful, every
+///   request must carry the headers from [`Loopback::local_api_headers`].
+#[derive(Clone, Debug)]
+pub struct Loopback {
+    /// The local address the server listens on.
+    pub addr: SocketAddr,
+    /// The password for the SOCKS5 proxy.
+    pub proxy_cred: Secret,
+    /// The basic auth password for the LocalAPI.
+    pub local_api_cred: Secret,
+}
+
+impl Loopback {
+    /// The URL of the SOCKS5 proxy, including credentials.
+    ///
+    /// This is of the form `socks5://tsnet:<cred>@<addr>`, suitable for `ALL_PROXY` or most
+    /// HTTP clients' proxy settings.
+    pub fn proxy_url(&self) -> String {
+        format!("socks5://tsnet:{}@{}", self.proxy_cred.expose(), self.addr)
+    }
+
+    /// The base URL of the LocalAPI, like `http://127.0.0.1:1234/localapi/v0/`.
+    pub fn local_api_url(&self) -> String {
+        format!("http://{}/localapi/v0/", self.addr)
+    }
+
+    /// The value of the `Authorization` header for LocalAPI requests.
+    pub fn local_api_auth(&self) -> String {
+        let token = STANDARD.encode(format!(":{}", self.local_api_cred.expose()));
+        format!("Basic {token}")
+    }
+
+    /// The headers (name and value) that must be sent with every LocalAPI request.
+    pub fn local_api_headers(&self) -> [(&'static str, String); 2] {
+        [
+            ("Authorization", self.local_api_auth()),
+            ("Sec-Tailscale", "localapi".to_string()),
+        ]
+    }
+}
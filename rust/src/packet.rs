@@ -0,0 +1,232 @@
+//! Datagram sockets on the tailnet.
+
+use std::{
+    io::{self, IoSlice, IoSliceMut},
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+    sync::Arc,
+};
+
+use nix::sys::uio::{readv, writev};
+
+use crate::{sys, Error, Inner};
+
+const HEADER_LEN: usize = sys::TAILSCALE_PACKET_HEADER_LEN as usize;
+
+/// A datagram socket on the tailnet, as created by
+/// [`Server::listen_packet`](crate::Server::listen_packet).
+///
+/// Like a bound [`UdpSocket`](std::net::UdpSocket), it can receive datagrams from any peer
+/// and send datagrams to any peer, preserving message boundaries.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use tsnet::ServerBuilder;
+///
+/// let ts = ServerBuilder::new().ephemeral().wait_for_up().build().unwrap();
+/// let (ip4, _) = ts.tailscale_ips().unwrap();
+/// let socket = ts.listen_packet(&format!("{}:5353", ip4.unwrap())).unwrap();
+///
+/// let mut buf = [0; 1500];
+/// loop {
+///     let (n, peer) = socket.recv_from(&mut buf).unwrap();
+///     socket.send_to(&buf[..n], peer).unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct PacketSocket {
+    fd: OwnedFd,
+    ts: Arc<Inner>,
+}
+
+impl PacketSocket {
+    pub(crate) fn new(fd: OwnedFd, ts: Arc<Inner>) -> Self {
+        PacketSocket { fd, ts }
+    }
+
+    /// Receive a single datagram, returning the number of bytes read and where it came from.
+    ///
+    /// This blocks until a datagram is available.
+    /// If the datagram is larger than `buf`, the excess bytes are discarded.
+    ///
+    /// Fails with [`Error::ServerClosed`], converted into an [`io::Error`], once the server
+    /// of this socket has been closed.
+    pub fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        let mut header = [0; HEADER_LEN];
+        let n = readv(
+            &self.fd,
+            &mut [IoSliceMut::new(&mut header), IoSliceMut::new(buf)],
+        )?;
+
+        if n == 0 {
+            return Err(Error::ServerClosed.into());
+        }
+        let addr = decode_addr(&header[..n.min(HEADER_LEN)])?;
+        Ok((n - HEADER_LEN, addr))
+    }
+
+    /// Send a single datagram to the given peer, returning the number of bytes sent.
+    pub fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let header = encode_addr(addr);
+        let n = writev(&self.fd, &[IoSlice::new(&header), IoSlice::new(buf)])?;
+        Ok(n.saturating_sub(HEADER_LEN))
+    }
+
+    /// Create a new independently owned handle to the same socket.
+    pub fn try_clone(&self) -> io::Result<PacketSocket> {
+        Ok(PacketSocket {
+            fd: self.fd.try_clone()?,
+            ts: self.ts.clone(),
+        })
+    }
+}
+
+impl AsFd for PacketSocket {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+impl AsRawFd for PacketSocket {
+    fn as_raw_fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+impl From<PacketSocket> for OwnedFd {
+    fn from(socket: PacketSocket) -> OwnedFd {
+        socket.fd
+    }
+}
+
+fn encode_addr(addr: SocketAddr) -> [u8; HEADER_LEN] {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+
+    let mut header = [0; HEADER_LEN];
+    header[..16].copy_from_slice(&ip.octets());
+    header[16..].copy_from_slice(&addr.port().to_be_bytes());
+    header
+}
+
+/// Decode the address header of a datagram, failing if it was cut short.
+///
+/// The header has no address family: IPv4 addresses are IPv4-mapped, so any 16 bytes are a
+/// valid address.
+fn decode_addr(header: &[u8]) -> io::Result<SocketAddr> {
+    let Ok(header) = <&[u8; HEADER_LEN]>::try_from(header) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "datagram without an address header",
+        ));
+    };
+
+    let mut ip = [0; 16];
+    ip.copy_from_slice(&header[..16]);
+    let ip = Ipv6Addr::from(ip);
+    let ip = match ip.to_ipv4_mapped() {
+        Some(ip) => IpAddr::V4(ip),
+        None => IpAddr::V6(ip),
+    };
+
+    Ok(SocketAddr::new(
+        ip,
+        u16::from_be_bytes([header[16], header[17]]),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ipv4_round_trip() {
+        let addr: SocketAddr = "100.64.0.1:5353".parse().unwrap();
+        let header = encode_addr(addr);
+        assert_eq!(
+            header,
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 100, 64, 0, 1, 0x14, 0xe9]
+        );
+        assert_eq!(decode_addr(&header).unwrap(), addr);
+    }
+
+    #[test]
+    fn ipv6_round_trip() {
+        for addr in [
+            "[fd7a:115c:a1e0::1]:443",
+            "[::1]:1",
+            "[::]:0",
+            "[::102:304]:65535",
+        ] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            let decoded = decode_addr(&encode_addr(addr)).unwrap();
+            assert_eq!(decoded, addr);
+            assert!(decoded.is_ipv6());
+        }
+    }
+
+    #[test]
+    fn truncated_header() {
+        let header = encode_addr("100.64.0.1:80".parse().unwrap());
+        for len in [0, 1, 16, HEADER_LEN - 1] {
+            let err = decode_addr(&header[..len]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    /// A socket like the ones the Go side hands out, and the Go end of it.
+    fn socket_pair() -> (PacketSocket, OwnedFd) {
+        use nix::sys::socket::{socketpair, AddressFamily, SockFlag, SockType};
+        let (fd, peer) = socketpair(
+            AddressFamily::Unix,
+            SockType::SeqPacket,
+            None,
+            SockFlag::empty(),
+        )
+        .unwrap();
+        // Already closed, so that dropping it doesn't call into the server.
+        let ts = Arc::new(Inner {
+            handle: -1,
+            closed: true.into(),
+            store: None,
+        });
+        (PacketSocket::new(fd, ts), peer)
+    }
+
+    #[test]
+    fn recv_from() {
+        let (socket, peer) = socket_pair();
+        let addr = "100.64.0.1:5353".parse().unwrap();
+        writev(
+            &peer,
+            &[IoSlice::new(&encode_addr(addr)), IoSlice::new(b"hi")],
+        )
+        .unwrap();
+
+        let mut buf = [0; 16];
+        assert_eq!(socket.recv_from(&mut buf).unwrap(), (2, addr));
+        assert_eq!(&buf[..2], b"hi");
+    }
+
+    #[test]
+    fn recv_from_closed_server() {
+        let (socket, peer) = socket_pair();
+        drop(peer);
+
+        let err = socket.recv_from(&mut [0; 16]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+        let err = err.into_inner().unwrap().downcast::<Error>().unwrap();
+        assert!(matches!(*err, Error::ServerClosed), "{err}");
+    }
+
+    #[test]
+    fn arbitrary_header_bytes() {
+        // There is no family byte to reject, so garbage still decodes without panicking.
+        let addr = decode_addr(&[0xff; HEADER_LEN]).unwrap();
+        assert_eq!(addr.port(), u16::MAX);
+        assert!(addr.is_ipv6());
+    }
+}
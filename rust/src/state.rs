@@ -0,0 +1,526 @@
+//! Keeping the state of a node elsewhere than in its state directory, or encrypted.
+
+#[cfg(feature = "encrypted-state")]
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use std::{
+    ffi::{c_char, c_int, c_void, CStr},
+    fmt, io,
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr, slice,
+    sync::Arc,
+};
+
+#[cfg(feature = "encrypted-state")]
+use base64::{engine::general_purpose::STANDARD, Engine};
+#[cfg(feature = "encrypted-state")]
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Nonce,
+};
+use nix::libc::{EINVAL, EIO, ENOENT, ERANGE};
+
+use crate::sys;
+
+/// Storage for the state of a node, like its machine and node keys.
+///
+/// The state is a set of values identified by keys. Set a store with
+/// [`ServerBuilder::store`](crate::ServerBuilder::store) to keep the state in a secrets
+/// backend, say, rather than in plaintext files under
+/// [`ServerBuilder::dir`](crate::ServerBuilder::dir).
+///
+/// The methods are called from threads of libtailscale, possibly concurrently, and block
+/// the node while they run.
+pub trait StateStore: Send + Sync + 'static {
+    /// Read the value of `key`, or `None` if there is none.
+    fn read_state(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Store `value` as the value of `key`, replacing any previous value.
+    fn write_state(&self, key: &str, value: &[u8]) -> io::Result<()>;
+}
+
+impl<S: StateStore + ?Sized> StateStore for Box<S> {
+    fn read_state(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        (**self).read_state(key)
+    }
+
+    fn write_state(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        (**self).write_state(key, value)
+    }
+}
+
+impl<S: StateStore + ?Sized> StateStore for Arc<S> {
+    fn read_state(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        (**self).read_state(key)
+    }
+
+    fn write_state(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        (**self).write_state(key, value)
+    }
+}
+
+/// The version of the format of encrypted values, their first byte.
+#[cfg(feature = "encrypted-state")]
+const ENCRYPTED_V1: u8 = 1;
+#[cfg(feature = "encrypted-state")]
+const NONCE_LEN: usize = 12;
+
+/// A [`StateStore`] encrypting every value it stores in another one.
+///
+/// Values are encrypted with ChaCha20-Poly1305 under a 256-bit key, and bound to their
+/// key, so that values can't be swapped around undetected.
+///
+/// Set with [`ServerBuilder::encrypt_state`](crate::ServerBuilder::encrypt_state), this
+/// encrypts the state directory or the store set with
+/// [`ServerBuilder::store`](crate::ServerBuilder::store).
+///
+/// Requires the `encrypted-state` feature.
+#[cfg(feature = "encrypted-state")]
+pub struct EncryptedStore<S> {
+    inner: S,
+    cipher: ChaCha20Poly1305,
+}
+
+#[cfg(feature = "encrypted-state")]
+impl<S: StateStore> EncryptedStore<S> {
+    /// Encrypt the values stored in `inner` with `key`.
+    pub fn new(inner: S, key: &[u8; 32]) -> Self {
+        EncryptedStore {
+            inner,
+            cipher: ChaCha20Poly1305::new(key.into()),
+        }
+    }
+
+    fn seal(&self, key: &str, value: &[u8]) -> io::Result<Vec<u8>> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = self
+            .cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: value,
+                    aad: key.as_bytes(),
+                },
+            )
+            .map_err(|_| io::Error::other(format!("can't encrypt state {key}")))?;
+
+        let mut out = Vec::with_capacity(1 + NONCE_LEN + sealed.len());
+        out.push(ENCRYPTED_V1);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&sealed);
+        Ok(out)
+    }
+
+    fn open(&self, key: &str, value: &[u8]) -> io::Result<Vec<u8>> {
+        let invalid =
+            |why| io::Error::new(io::ErrorKind::InvalidData, format!("state {key} {why}"));
+        match value.first() {
+            Some(&ENCRYPTED_V1) if value.len() > NONCE_LEN => {}
+            _ => return Err(invalid("is not encrypted")),
+        }
+        let (nonce, sealed) = value[1..].split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: sealed,
+                    aad: key.as_bytes(),
+                },
+            )
+            .map_err(|_| invalid("can't be decrypted, is the key wrong?"))
+    }
+}
+
+#[cfg(feature = "encrypted-state")]
+impl<S: StateStore> StateStore for EncryptedStore<S> {
+    fn read_state(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.inner.read_state(key)? {
+            Some(value) => self.open(key, &value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn write_state(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        self.inner.write_state(key, &self.seal(key, value)?)
+    }
+}
+
+#[cfg(feature = "encrypted-state")]
+impl<S: fmt::Debug> fmt::Debug for EncryptedStore<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncryptedStore")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The file tsnet keeps the state in, under the state directory.
+#[cfg(feature = "encrypted-state")]
+const STATE_FILE: &str = "tailscaled.state";
+/// The file [`encrypted_dir`] keeps the encrypted state in, under the state directory.
+#[cfg(feature = "encrypted-state")]
+const ENCRYPTED_STATE_FILE: &str = "tailscaled.state.enc";
+
+/// A [`StateStore`] keeping values in a JSON file, the way tsnet does: as an object
+/// mapping each key to its value in base64.
+#[cfg(feature = "encrypted-state")]
+#[derive(Debug)]
+pub(crate) struct FileStore {
+    path: PathBuf,
+    values: Mutex<HashMap<String, Vec<u8>>>,
+}
+
+#[cfg(feature = "encrypted-state")]
+impl FileStore {
+    /// Open the store in the file at `path`, which is created on the first write.
+    pub(crate) fn open(path: PathBuf) -> io::Result<Self> {
+        let values = match fs::read(&path) {
+            Ok(json) => {
+                let values: HashMap<String, String> = serde_json::from_slice(&json)?;
+                values
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let value = STANDARD
+                            .decode(value)
+                            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                        Ok((key, value))
+                    })
+                    .collect::<io::Result<_>>()?
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(err) => return Err(err),
+        };
+        Ok(FileStore {
+            path,
+            values: Mutex::new(values),
+        })
+    }
+
+    /// Write all values to the file, replacing it atomically.
+    fn save(&self, values: &HashMap<String, Vec<u8>>) -> io::Result<()> {
+        let values: HashMap<&str, String> = values
+            .iter()
+            .map(|(key, value)| (key.as_str(), STANDARD.encode(value)))
+            .collect();
+        let json = serde_json::to_vec_pretty(&values)?;
+
+        let tmp = self.path.with_extension("tmp");
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)?;
+        file.write_all(&json)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+#[cfg(feature = "encrypted-state")]
+impl StateStore for FileStore {
+    fn read_state(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.values.lock().unwrap().get(key).cloned())
+    }
+
+    fn write_state(&self, key: &str, value: &[u8]) -> io::Result<()> {
+        let mut values = self.values.lock().unwrap();
+        let old = values.insert(key.to_string(), value.to_vec());
+        self.save(&values).inspect_err(|_| {
+            // Keep the values as they are on disk.
+            match old {
+                Some(old) => values.insert(key.to_string(), old),
+                None => values.remove(key),
+            };
+        })
+    }
+}
+
+/// An encrypted store for the state directory `dir`.
+///
+/// A plaintext state left in the directory by tsnet is encrypted into the store, replacing
+/// its state, and then removed. As tsnet doesn't write that file while the state is
+/// encrypted, it is always the more recent one.
+#[cfg(feature = "encrypted-state")]
+pub(crate) fn encrypted_dir(dir: &Path, key: &[u8; 32]) -> io::Result<EncryptedStore<FileStore>> {
+    fs::create_dir_all(dir)?;
+    let store = EncryptedStore::new(FileStore::open(dir.join(ENCRYPTED_STATE_FILE))?, key);
+
+    let plain_path = dir.join(STATE_FILE);
+    if plain_path.exists() {
+        let plain = FileStore::open(plain_path.clone())?;
+        let values = plain.values.into_inner().unwrap();
+        let mut sealed = HashMap::with_capacity(values.len());
+        for (key, value) in values {
+            let value = store.seal(&key, &value)?;
+            sealed.insert(key, value);
+        }
+        // All at once, so that a failure leaves the plaintext state to migrate next time.
+        store.inner.save(&sealed)?;
+        *store.inner.values.lock().unwrap() = sealed;
+        fs::remove_file(&plain_path)?;
+        log::info!("encrypted the plaintext state in {}", dir.display());
+    }
+
+    Ok(store)
+}
+
+/// A [`StateStore`] registered with a server, which must outlive it.
+pub(crate) struct Registered(Box<Box<dyn StateStore>>);
+
+impl Registered {
+    pub(crate) fn new(store: Box<dyn StateStore>) -> Self {
+        Registered(Box::new(store))
+    }
+
+    /// Register the store with the server `handle`, see `tailscale_set_state_store`.
+    pub(crate) fn set(&self, handle: sys::tailscale, error: *mut sys::tailscale_error) -> c_int {
+        let ctx = &*self.0 as *const Box<dyn StateStore> as *mut c_void;
+        unsafe {
+            sys::tailscale_set_state_store_err(
+                handle,
+                Some(read_state),
+                Some(write_state),
+                ctx,
+                error,
+            )
+        }
+    }
+}
+
+impl fmt::Debug for Registered {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StateStore")
+    }
+}
+
+/// Run a callback of the store, turning errors and panics into an errno.
+fn callback(
+    op: &str,
+    ctx: *mut c_void,
+    key: *const c_char,
+    f: impl FnOnce(&dyn StateStore, &str) -> io::Result<c_int>,
+) -> c_int {
+    let store = unsafe { &*(ctx as *const Box<dyn StateStore>) };
+    let Ok(key) = unsafe { CStr::from_ptr(key) }.to_str() else {
+        return EINVAL;
+    };
+
+    match catch_unwind(AssertUnwindSafe(|| f(&**store, key))) {
+        Ok(Ok(rc)) => rc,
+        Ok(Err(err)) if err.kind() == io::ErrorKind::NotFound => ENOENT,
+        Ok(Err(err)) => {
+            log::error!("state store: {op} {key}: {err}");
+            match err.raw_os_error() {
+                Some(errno) if errno != 0 && errno != ERANGE => errno,
+                _ => EIO,
+            }
+        }
+        Err(_) => {
+            log::error!("state store: {op} {key}: panicked");
+            EIO
+        }
+    }
+}
+
+unsafe extern "C" fn read_state(
+    ctx: *mut c_void,
+    key: *const c_char,
+    buf: *mut c_char,
+    buflen: usize,
+    len_out: *mut usize,
+) -> c_int {
+    callback("reading", ctx, key, |store, key| {
+        let Some(value) = store.read_state(key)? else {
+            return Ok(ENOENT);
+        };
+        unsafe { *len_out = value.len() };
+        if value.len() > buflen {
+            return Ok(ERANGE);
+        }
+        unsafe { ptr::copy_nonoverlapping(value.as_ptr(), buf.cast(), value.len()) };
+        Ok(0)
+    })
+}
+
+unsafe extern "C" fn write_state(
+    ctx: *mut c_void,
+    key: *const c_char,
+    value: *const c_char,
+    len: usize,
+) -> c_int {
+    let value = match len {
+        0 => &[][..],
+        len => unsafe { slice::from_raw_parts(value.cast(), len) },
+    };
+    callback("writing", ctx, key, |store, key| {
+        store.write_state(key, value)?;
+        Ok(0)
+    })
+}
+
+#[cfg(all(test, feature = "encrypted-state"))]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    #[derive(Default)]
+    struct MemStore(Mutex<HashMap<String, Vec<u8>>>);
+
+    impl StateStore for MemStore {
+        fn read_state(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+            Ok(self.0.lock().unwrap().get(key).cloned())
+        }
+
+        fn write_state(&self, key: &str, value: &[u8]) -> io::Result<()> {
+            self.0.lock().unwrap().insert(key.into(), value.into());
+            Ok(())
+        }
+    }
+
+    /// A new empty directory, removed once dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("tsnet-state-test-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn sealed(store: &EncryptedStore<MemStore>, key: &str) -> Vec<u8> {
+        store.inner.read_state(key).unwrap().unwrap()
+    }
+
+    #[test]
+    fn seal_open_round_trip() {
+        let store = EncryptedStore::new(MemStore::default(), &KEY);
+        store.write_state("_machinekey", b"secret").unwrap();
+        assert_eq!(
+            store.read_state("_machinekey").unwrap().as_deref(),
+            Some(&b"secret"[..])
+        );
+        assert_eq!(store.read_state("missing").unwrap(), None);
+
+        let value = sealed(&store, "_machinekey");
+        assert_eq!(value[0], ENCRYPTED_V1);
+        assert!(!value.windows(6).any(|w| w == b"secret"));
+    }
+
+    #[test]
+    fn open_rejects_tampered_value() {
+        let store = EncryptedStore::new(MemStore::default(), &KEY);
+        store.write_state("k", b"value").unwrap();
+        let mut value = sealed(&store, "k");
+        *value.last_mut().unwrap() ^= 1;
+        store.inner.write_state("k", &value).unwrap();
+
+        let err = store.read_state("k").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn open_rejects_wrong_key() {
+        let store = EncryptedStore::new(MemStore::default(), &KEY);
+        store.write_state("k", b"value").unwrap();
+        let other = EncryptedStore::new(store.inner, &[8; 32]);
+
+        let err = other.read_state("k").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn open_rejects_value_of_other_key() {
+        let store = EncryptedStore::new(MemStore::default(), &KEY);
+        store.write_state("a", b"value of a").unwrap();
+        store.inner.write_state("b", &sealed(&store, "a")).unwrap();
+
+        let err = store.read_state("b").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(store.read_state("a").is_ok());
+    }
+
+    #[test]
+    fn open_rejects_unknown_version() {
+        let store = EncryptedStore::new(MemStore::default(), &KEY);
+        store.write_state("k", b"value").unwrap();
+        let mut value = sealed(&store, "k");
+        value[0] = ENCRYPTED_V1 + 1;
+        store.inner.write_state("k", &value).unwrap();
+        assert!(store.read_state("k").is_err());
+
+        // Nor a plaintext value, or one too short to hold a nonce.
+        store.inner.write_state("k", b"plaintext").unwrap();
+        assert!(store.read_state("k").is_err());
+        store.inner.write_state("k", &[ENCRYPTED_V1; 4]).unwrap();
+        assert!(store.read_state("k").is_err());
+    }
+
+    fn write_plaintext(dir: &Path, values: &[(&str, &[u8])]) {
+        let plain = FileStore::open(dir.join(STATE_FILE)).unwrap();
+        for (key, value) in values {
+            plain.write_state(key, value).unwrap();
+        }
+    }
+
+    #[test]
+    fn encrypted_dir_migrates_plaintext_state() {
+        let dir = TempDir::new("migrate");
+        write_plaintext(&dir.0, &[("_machinekey", b"hunter2"), ("_profiles", b"{}")]);
+
+        let store = encrypted_dir(&dir.0, &KEY).unwrap();
+        assert!(!dir.0.join(STATE_FILE).exists());
+        assert_eq!(
+            store.read_state("_machinekey").unwrap().as_deref(),
+            Some(&b"hunter2"[..])
+        );
+
+        // The values on disk are encrypted, and read back by a new store.
+        let on_disk = fs::read(dir.0.join(ENCRYPTED_STATE_FILE)).unwrap();
+        assert!(!on_disk.windows(7).any(|w| w == b"hunter2"));
+        drop(store);
+        let store = encrypted_dir(&dir.0, &KEY).unwrap();
+        assert_eq!(
+            store.read_state("_profiles").unwrap().as_deref(),
+            Some(&b"{}"[..])
+        );
+    }
+
+    #[test]
+    fn encrypted_dir_keeps_plaintext_state_if_save_fails() {
+        let dir = TempDir::new("migrate-fail");
+        write_plaintext(&dir.0, &[("_machinekey", b"hunter2")]);
+
+        // FileStore::save can't create its temporary file over a directory.
+        let tmp = dir.0.join(ENCRYPTED_STATE_FILE).with_extension("tmp");
+        fs::create_dir(&tmp).unwrap();
+        assert!(encrypted_dir(&dir.0, &KEY).is_err());
+        assert!(dir.0.join(STATE_FILE).exists());
+        assert!(!dir.0.join(ENCRYPTED_STATE_FILE).exists());
+
+        // And it is migrated on the next try.
+        fs::remove_dir(&tmp).unwrap();
+        let store = encrypted_dir(&dir.0, &KEY).unwrap();
+        assert!(!dir.0.join(STATE_FILE).exists());
+        assert_eq!(
+            store.read_state("_machinekey").unwrap().as_deref(),
+            Some(&b"hunter2"[..])
+        );
+    }
+}
@@ -54,9 +54,93 @@ extern "C" {
 extern "C" {
     pub fn tailscale_up(sd: tailscale) -> ::std::os::raw::c_int;
 }
+extern "C" {
+    pub fn tailscale_up_timeout(
+        sd: tailscale,
+        timeout_ms: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_up_status(
+        sd: tailscale,
+        buf: *mut ::std::os::raw::c_char,
+        buflen: usize,
+    ) -> ::std::os::raw::c_int;
+}
 extern "C" {
     pub fn tailscale_close(sd: tailscale) -> ::std::os::raw::c_int;
 }
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct tailscale_shutdown_report {
+    pub listeners_closed: ::std::os::raw::c_int,
+    pub conns_drained: ::std::os::raw::c_int,
+    pub conns_forced: ::std::os::raw::c_int,
+    pub logged_out: ::std::os::raw::c_int,
+}
+#[test]
+fn bindgen_test_layout_tailscale_shutdown_report() {
+    const UNINIT: ::std::mem::MaybeUninit<tailscale_shutdown_report> =
+        ::std::mem::MaybeUninit::uninit();
+    let ptr = UNINIT.as_ptr();
+    assert_eq!(
+        ::std::mem::size_of::<tailscale_shutdown_report>(),
+        16usize,
+        concat!("Size of: ", stringify!(tailscale_shutdown_report))
+    );
+    assert_eq!(
+        ::std::mem::align_of::<tailscale_shutdown_report>(),
+        4usize,
+        concat!("Alignment of ", stringify!(tailscale_shutdown_report))
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).listeners_closed) as usize - ptr as usize },
+        0usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(tailscale_shutdown_report),
+            "::",
+            stringify!(listeners_closed)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).conns_drained) as usize - ptr as usize },
+        4usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(tailscale_shutdown_report),
+            "::",
+            stringify!(conns_drained)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).conns_forced) as usize - ptr as usize },
+        8usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(tailscale_shutdown_report),
+            "::",
+            stringify!(conns_forced)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).logged_out) as usize - ptr as usize },
+        12usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(tailscale_shutdown_report),
+            "::",
+            stringify!(logged_out)
+        )
+    );
+}
+extern "C" {
+    pub fn tailscale_shutdown(
+        sd: tailscale,
+        timeout_ms: ::std::os::raw::c_int,
+        report_out: *mut tailscale_shutdown_report,
+    ) -> ::std::os::raw::c_int;
+}
 extern "C" {
     pub fn tailscale_set_dir(
         sd: tailscale,
@@ -90,6 +174,13 @@ extern "C" {
 extern "C" {
     pub fn tailscale_set_logfd(sd: tailscale, fd: ::std::os::raw::c_int) -> ::std::os::raw::c_int;
 }
+extern "C" {
+    pub fn tailscale_getips(
+        sd: tailscale,
+        buf: *mut ::std::os::raw::c_char,
+        buflen: usize,
+    ) -> ::std::os::raw::c_int;
+}
 pub type tailscale_conn = ::std::os::raw::c_int;
 extern "C" {
     pub fn tailscale_dial(
@@ -108,12 +199,80 @@ extern "C" {
         listener_out: *mut tailscale_listener,
     ) -> ::std::os::raw::c_int;
 }
+extern "C" {
+    pub fn tailscale_listen_tls(
+        sd: tailscale,
+        network: *const ::std::os::raw::c_char,
+        addr: *const ::std::os::raw::c_char,
+        listener_out: *mut tailscale_listener,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_listen_funnel(
+        sd: tailscale,
+        network: *const ::std::os::raw::c_char,
+        addr: *const ::std::os::raw::c_char,
+        funnel_only: ::std::os::raw::c_int,
+        listener_out: *mut tailscale_listener,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_cert_domains(
+        sd: tailscale,
+        buf: *mut ::std::os::raw::c_char,
+        buflen: usize,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_cert_pair(
+        sd: tailscale,
+        domain: *const ::std::os::raw::c_char,
+        buf: *mut ::std::os::raw::c_char,
+        buflen: usize,
+    ) -> ::std::os::raw::c_int;
+}
 extern "C" {
     pub fn tailscale_accept(
         listener: tailscale_listener,
         conn_out: *mut tailscale_conn,
     ) -> ::std::os::raw::c_int;
 }
+extern "C" {
+    pub fn tailscale_accept_addr(
+        listener: tailscale_listener,
+        conn_out: *mut tailscale_conn,
+        remote_addr_out: *mut ::std::os::raw::c_char,
+        addrlen: usize,
+    ) -> ::std::os::raw::c_int;
+}
+pub const TAILSCALE_CONN_FUNNEL: u32 = 1;
+extern "C" {
+    pub fn tailscale_accept_info(
+        listener: tailscale_listener,
+        conn_out: *mut tailscale_conn,
+        remote_addr_out: *mut ::std::os::raw::c_char,
+        addrlen: usize,
+        flags_out: *mut ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_whois(
+        sd: tailscale,
+        addr: *const ::std::os::raw::c_char,
+        buf: *mut ::std::os::raw::c_char,
+        buflen: usize,
+    ) -> ::std::os::raw::c_int;
+}
+pub type tailscale_packet = ::std::os::raw::c_int;
+pub const TAILSCALE_PACKET_HEADER_LEN: u32 = 18;
+extern "C" {
+    pub fn tailscale_listen_packet(
+        sd: tailscale,
+        network: *const ::std::os::raw::c_char,
+        addr: *const ::std::os::raw::c_char,
+        packet_out: *mut tailscale_packet,
+    ) -> ::std::os::raw::c_int;
+}
 extern "C" {
     pub fn tailscale_loopback(
         sd: tailscale,
@@ -130,3 +289,292 @@ extern "C" {
         buflen: usize,
     ) -> ::std::os::raw::c_int;
 }
+pub const TAILSCALE_ERR_NONE: u32 = 0;
+pub const TAILSCALE_ERR_OTHER: u32 = 1;
+pub const TAILSCALE_ERR_NEEDS_LOGIN: u32 = 2;
+pub const TAILSCALE_ERR_AUTHKEY_INVALID: u32 = 3;
+pub const TAILSCALE_ERR_CONTROL_UNREACHABLE: u32 = 4;
+pub const TAILSCALE_ERR_CONN_REFUSED: u32 = 5;
+pub const TAILSCALE_ERR_TIMEOUT: u32 = 6;
+pub const TAILSCALE_ERR_ADDR_IN_USE: u32 = 7;
+pub const TAILSCALE_ERR_UNKNOWN_HANDLE: u32 = 8;
+pub const TAILSCALE_ERR_SERVER_CLOSED: u32 = 9;
+extern "C" {
+    pub fn tailscale_errcode(sd: tailscale) -> ::std::os::raw::c_int;
+}
+pub type tailscale_error = ::std::os::raw::c_int;
+extern "C" {
+    pub fn tailscale_start_err(
+        sd: tailscale,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_up_err(sd: tailscale, err_out: *mut tailscale_error) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_up_timeout_err(
+        sd: tailscale,
+        timeout_ms: ::std::os::raw::c_int,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_up_status_err(
+        sd: tailscale,
+        buf: *mut ::std::os::raw::c_char,
+        buflen: usize,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_set_dir_err(
+        sd: tailscale,
+        dir: *const ::std::os::raw::c_char,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_set_hostname_err(
+        sd: tailscale,
+        hostname: *const ::std::os::raw::c_char,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_set_authkey_err(
+        sd: tailscale,
+        authkey: *const ::std::os::raw::c_char,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_set_control_url_err(
+        sd: tailscale,
+        control_url: *const ::std::os::raw::c_char,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_set_ephemeral_err(
+        sd: tailscale,
+        ephemeral: ::std::os::raw::c_int,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_set_logfd_err(
+        sd: tailscale,
+        fd: ::std::os::raw::c_int,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_getips_err(
+        sd: tailscale,
+        buf: *mut ::std::os::raw::c_char,
+        buflen: usize,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_dial_err(
+        sd: tailscale,
+        network: *const ::std::os::raw::c_char,
+        addr: *const ::std::os::raw::c_char,
+        conn_out: *mut tailscale_conn,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_listen_err(
+        sd: tailscale,
+        network: *const ::std::os::raw::c_char,
+        addr: *const ::std::os::raw::c_char,
+        listener_out: *mut tailscale_listener,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_listen_tls_err(
+        sd: tailscale,
+        network: *const ::std::os::raw::c_char,
+        addr: *const ::std::os::raw::c_char,
+        listener_out: *mut tailscale_listener,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_listen_funnel_err(
+        sd: tailscale,
+        network: *const ::std::os::raw::c_char,
+        addr: *const ::std::os::raw::c_char,
+        funnel_only: ::std::os::raw::c_int,
+        listener_out: *mut tailscale_listener,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_listen_packet_err(
+        sd: tailscale,
+        network: *const ::std::os::raw::c_char,
+        addr: *const ::std::os::raw::c_char,
+        packet_out: *mut tailscale_packet,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_cert_domains_err(
+        sd: tailscale,
+        buf: *mut ::std::os::raw::c_char,
+        buflen: usize,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_cert_pair_err(
+        sd: tailscale,
+        domain: *const ::std::os::raw::c_char,
+        buf: *mut ::std::os::raw::c_char,
+        buflen: usize,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_whois_err(
+        sd: tailscale,
+        addr: *const ::std::os::raw::c_char,
+        buf: *mut ::std::os::raw::c_char,
+        buflen: usize,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_loopback_err(
+        sd: tailscale,
+        addr_out: *mut ::std::os::raw::c_char,
+        addrlen: usize,
+        proxy_cred_out: *mut ::std::os::raw::c_char,
+        local_api_cred_out: *mut ::std::os::raw::c_char,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_error_code(err: tailscale_error) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_error_msg(
+        err: tailscale_error,
+        buf: *mut ::std::os::raw::c_char,
+        buflen: usize,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_error_free(err: tailscale_error);
+}
+pub type tailscale_op = ::std::os::raw::c_int;
+pub const TAILSCALE_OP_ERRMSG_LEN: u32 = 4096;
+extern "C" {
+    pub fn tailscale_dial_start(
+        sd: tailscale,
+        network: *const ::std::os::raw::c_char,
+        addr: *const ::std::os::raw::c_char,
+        op_out: *mut tailscale_op,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_up_start(
+        sd: tailscale,
+        op_out: *mut tailscale_op,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_op_result(
+        op: tailscale_op,
+        conn_out: *mut tailscale_conn,
+        code_out: *mut ::std::os::raw::c_int,
+        errmsg: *mut ::std::os::raw::c_char,
+        errmsglen: usize,
+    ) -> ::std::os::raw::c_int;
+}
+pub type tailscale_state_read_fn = ::std::option::Option<
+    unsafe extern "C" fn(
+        ctx: *mut ::std::os::raw::c_void,
+        key: *const ::std::os::raw::c_char,
+        buf: *mut ::std::os::raw::c_char,
+        buflen: usize,
+        len_out: *mut usize,
+    ) -> ::std::os::raw::c_int,
+>;
+pub type tailscale_state_write_fn = ::std::option::Option<
+    unsafe extern "C" fn(
+        ctx: *mut ::std::os::raw::c_void,
+        key: *const ::std::os::raw::c_char,
+        value: *const ::std::os::raw::c_char,
+        len: usize,
+    ) -> ::std::os::raw::c_int,
+>;
+extern "C" {
+    pub fn tailscale_set_state_store(
+        sd: tailscale,
+        read: tailscale_state_read_fn,
+        write: tailscale_state_write_fn,
+        ctx: *mut ::std::os::raw::c_void,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_set_state_store_err(
+        sd: tailscale,
+        read: tailscale_state_read_fn,
+        write: tailscale_state_write_fn,
+        ctx: *mut ::std::os::raw::c_void,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_set_state_in_memory(sd: tailscale) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_set_state_in_memory_err(
+        sd: tailscale,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_get_state(
+        sd: tailscale,
+        key: *const ::std::os::raw::c_char,
+        buf: *mut ::std::os::raw::c_char,
+        buflen: usize,
+        len_out: *mut usize,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_get_state_err(
+        sd: tailscale,
+        key: *const ::std::os::raw::c_char,
+        buf: *mut ::std::os::raw::c_char,
+        buflen: usize,
+        len_out: *mut usize,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_set_state(
+        sd: tailscale,
+        key: *const ::std::os::raw::c_char,
+        value: *const ::std::os::raw::c_char,
+        len: usize,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn tailscale_set_state_err(
+        sd: tailscale,
+        key: *const ::std::os::raw::c_char,
+        value: *const ::std::os::raw::c_char,
+        len: usize,
+        err_out: *mut tailscale_error,
+    ) -> ::std::os::raw::c_int;
+}
@@ -0,0 +1,400 @@
+//! TLS certificates from the embedded node, for use with [`rustls`].
+//!
+//! Requires the `rustls` feature.
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use std::sync::Arc;
+//! use tsnet::{tls::CertResolver, ServerBuilder};
+//!
+//! let ts = ServerBuilder::new().ephemeral().wait_for_up().build().unwrap();
+//! let resolver = CertResolver::new(&ts).unwrap();
+//!
+//! let config = rustls::ServerConfig::builder()
+//!     .with_no_client_auth()
+//!     .with_cert_resolver(Arc::new(resolver));
+//! ```
+
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::{Arc, Condvar, Mutex, Weak},
+    thread,
+    time::{Duration, Instant},
+};
+
+use rustls::{
+    crypto::CryptoProvider,
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+
+use crate::{cert_domains, cert_pair, Error, Inner, Result, Server};
+
+/// How long to use a certificate before asking the node for it again.
+///
+/// The node renews certificates well before they expire, this only needs to be frequent
+/// enough to pick up renewals.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(12 * 60 * 60);
+/// How long to wait before trying again to get a certificate that couldn't be had.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// How often to ask the node again for the names it has certificates for.
+const DOMAINS_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// How often the background thread checks for work, when not woken up.
+const TICK: Duration = Duration::from_secs(30);
+
+/// A [`ResolvesServerCert`] serving the certificates of an embedded node.
+///
+/// Only the names in [`Server::cert_domains`] are served, and clients not sending SNI get the
+/// certificate of the first of them. Certificates are fetched with [`Server::cert_pair`] by a
+/// background thread, which keeps them cached and fetched again periodically to pick up
+/// renewals, so handshakes never wait on the node. Until the certificate of a name has been
+/// fetched, or while it can't be, handshakes for that name fail.
+pub struct CertResolver {
+    shared: Arc<Shared>,
+}
+
+/// The state of a resolver, shared with its background thread.
+struct Shared {
+    ts: Arc<Inner>,
+    provider: Arc<CryptoProvider>,
+    state: Mutex<State>,
+    wake: Condvar,
+}
+
+/// The certificates of a resolver.
+#[derive(Default)]
+struct State {
+    /// The names the node has certificates for, and when they were last asked for.
+    domains: Vec<String>,
+    domains_fetched: Option<Instant>,
+    certs: HashMap<String, Entry>,
+    /// Set when a handshake needs a certificate that wasn't fetched yet, for the background
+    /// thread to fetch it right away, even if it was busy refreshing when woken up.
+    pending: bool,
+    /// Set once the resolver is dropped, to stop the background thread.
+    stopped: bool,
+}
+
+/// The certificate of a name, or the lack thereof.
+struct Entry {
+    /// The certificate, kept even when refreshing it fails.
+    key: Option<Arc<CertifiedKey>>,
+    /// When the certificate was last fetched, or last failed to be.
+    fetched: Instant,
+    failed: bool,
+}
+
+impl Entry {
+    fn due(&self, now: Instant) -> bool {
+        let interval = if self.failed {
+            RETRY_INTERVAL
+        } else {
+            REFRESH_INTERVAL
+        };
+        now.duration_since(self.fetched) >= interval
+    }
+}
+
+impl State {
+    /// The certificate to serve for `name`, or the first domain without one, and whether the
+    /// background thread should be woken up to fetch it, which is then pending.
+    fn lookup(&mut self, name: Option<&str>) -> (Option<Arc<CertifiedKey>>, bool) {
+        let domain = match name {
+            Some(name) => self
+                .domains
+                .iter()
+                .find(|domain| domain.eq_ignore_ascii_case(name)),
+            None => self.domains.first(),
+        };
+        // Names the node has no certificates for are never fetched.
+        let Some(domain) = domain else {
+            return (None, false);
+        };
+        match self.certs.get(domain) {
+            Some(entry) => (entry.key.clone(), false),
+            None => {
+                self.pending = true;
+                (None, true)
+            }
+        }
+    }
+
+    /// The domains whose certificates are to be fetched.
+    fn due(&self, now: Instant) -> Vec<String> {
+        self.domains
+            .iter()
+            .filter(|domain| self.certs.get(*domain).is_none_or(|entry| entry.due(now)))
+            .cloned()
+            .collect()
+    }
+
+    fn domains_due(&self, now: Instant) -> bool {
+        match self.domains_fetched {
+            // Before the node is up, it has no domains yet.
+            Some(_) if self.domains.is_empty() => true,
+            Some(fetched) => now.duration_since(fetched) >= DOMAINS_INTERVAL,
+            None => true,
+        }
+    }
+
+    fn set_domains(&mut self, domains: Vec<String>, now: Instant) {
+        self.certs.retain(|domain, _| domains.contains(domain));
+        self.domains = domains;
+        self.domains_fetched = Some(now);
+    }
+}
+
+impl CertResolver {
+    /// Create a resolver for the given node, using the process-default [`CryptoProvider`].
+    ///
+    /// Fails if no default provider is installed, see [`CryptoProvider::install_default`].
+    pub fn new(server: &Server) -> Result<Self> {
+        let provider = CryptoProvider::get_default().ok_or_else(|| {
+            Error::TSNet("no process-default rustls CryptoProvider is installed".to_string())
+        })?;
+        Ok(Self::with_provider(server, provider.clone()))
+    }
+
+    /// Create a resolver for the given node, using the given [`CryptoProvider`] to load keys.
+    ///
+    /// This starts fetching the certificates of the node in the background right away.
+    pub fn with_provider(server: &Server, provider: Arc<CryptoProvider>) -> Self {
+        let shared = Arc::new(Shared {
+            ts: server.inner.clone(),
+            provider,
+            state: Mutex::new(State::default()),
+            wake: Condvar::new(),
+        });
+
+        let weak = Arc::downgrade(&shared);
+        let spawned = thread::Builder::new()
+            .name("tsnet-cert-resolver".to_string())
+            .spawn(move || refresh_loop(weak));
+        if let Err(err) = spawned {
+            log::error!("can't start fetching certificates: {err}");
+        }
+        CertResolver { shared }
+    }
+}
+
+/// Fetch certificates as they are due, until the resolver or the server is gone.
+fn refresh_loop(shared: Weak<Shared>) {
+    loop {
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+        if !shared.refresh() || !wait_for_work(&shared.state, &shared.wake, TICK) {
+            return;
+        }
+    }
+}
+
+/// Wait up to `timeout` for a certificate to be needed, returning false once stopped.
+///
+/// This doesn't wait at all if one was needed since the last wait.
+fn wait_for_work(state: &Mutex<State>, wake: &Condvar, timeout: Duration) -> bool {
+    let state = state.lock().unwrap();
+    let (mut state, _) = wake
+        .wait_timeout_while(state, timeout, |state| !state.stopped && !state.pending)
+        .unwrap();
+    state.pending = false;
+    !state.stopped
+}
+
+impl Shared {
+    /// Fetch what is due, returning false once the server is closed.
+    fn refresh(&self) -> bool {
+        let Ok(handle) = self.ts.handle() else {
+            return false;
+        };
+
+        let now = Instant::now();
+        if self.state.lock().unwrap().domains_due(now) {
+            match cert_domains(handle) {
+                Ok(domains) => self.state.lock().unwrap().set_domains(domains, now),
+                Err(Error::ServerClosed | Error::UnknownHandle) => return false,
+                Err(err) => log::warn!("can't get certificate domains: {err}"),
+            }
+        }
+
+        let due = self.state.lock().unwrap().due(now);
+        for domain in due {
+            let fetched = self.fetch(&domain);
+            let mut state = self.state.lock().unwrap();
+            let key = state.certs.remove(&domain).and_then(|entry| entry.key);
+            let entry = match fetched {
+                Ok(key) => Entry {
+                    key: Some(key),
+                    fetched: Instant::now(),
+                    failed: false,
+                },
+                Err(err) => {
+                    log::warn!("can't get certificate for {domain}: {err}");
+                    // Keep serving the previous certificate until it can be refreshed.
+                    Entry {
+                        key,
+                        fetched: Instant::now(),
+                        failed: true,
+                    }
+                }
+            };
+            state.certs.insert(domain, entry);
+        }
+        true
+    }
+
+    fn fetch(&self, domain: &str) -> Result<Arc<CertifiedKey>> {
+        let (certs, key) = cert_pair(self.ts.handle()?, domain)?;
+        let key = CertifiedKey::from_der(certs, key, &self.provider)
+            .map_err(|err| Error::TSNet(format!("can't load certificate for {domain}: {err}")))?;
+        Ok(Arc::new(key))
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let (key, wake) = self
+            .shared
+            .state
+            .lock()
+            .unwrap()
+            .lookup(client_hello.server_name());
+        if wake {
+            self.shared.wake.notify_one();
+        }
+        key
+    }
+}
+
+/// Stops the background thread.
+impl Drop for CertResolver {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().stopped = true;
+        self.shared.wake.notify_one();
+    }
+}
+
+impl fmt::Debug for CertResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CertResolver")
+            .field("ts", &self.shared.ts.handle)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(domains: &[&str], now: Instant) -> State {
+        let mut state = State::default();
+        state.set_domains(domains.iter().map(|d| d.to_string()).collect(), now);
+        state
+    }
+
+    fn failed(fetched: Instant) -> Entry {
+        Entry {
+            key: None,
+            fetched,
+            failed: true,
+        }
+    }
+
+    #[test]
+    fn unknown_names_are_never_fetched() {
+        let now = Instant::now();
+        let mut state = state(&["web.tail1234.ts.net"], now);
+        assert!(matches!(
+            state.lookup(Some("evil.example.com")),
+            (None, false)
+        ));
+        assert!(matches!(
+            state.lookup(Some("other.tail1234.ts.net")),
+            (None, false)
+        ));
+        assert!(!state.pending);
+        assert_eq!(state.due(now), ["web.tail1234.ts.net"]);
+    }
+
+    #[test]
+    fn known_names_wake_the_fetcher() {
+        let mut state = state(&["web.tail1234.ts.net"], Instant::now());
+        assert!(matches!(
+            state.lookup(Some("WEB.tail1234.ts.net")),
+            (None, true)
+        ));
+        assert!(state.pending);
+        assert!(matches!(state.lookup(None), (None, true)));
+        assert!(matches!(State::default().lookup(None), (None, false)));
+    }
+
+    #[test]
+    fn failures_are_cached() {
+        let now = Instant::now();
+        let mut state = state(&["web.tail1234.ts.net"], now);
+        state
+            .certs
+            .insert("web.tail1234.ts.net".to_string(), failed(now));
+
+        // A failure is neither fetched again on lookup nor before the retry interval.
+        assert!(matches!(
+            state.lookup(Some("web.tail1234.ts.net")),
+            (None, false)
+        ));
+        assert!(state.due(now + RETRY_INTERVAL / 2).is_empty());
+        assert_eq!(state.due(now + RETRY_INTERVAL), ["web.tail1234.ts.net"]);
+    }
+
+    #[test]
+    fn certificates_are_refreshed() {
+        let now = Instant::now();
+        let entry = Entry {
+            key: None,
+            fetched: now,
+            failed: false,
+        };
+        assert!(!entry.due(now + RETRY_INTERVAL));
+        assert!(entry.due(now + REFRESH_INTERVAL));
+    }
+
+    #[test]
+    fn domains_are_refreshed() {
+        let now = Instant::now();
+        assert!(State::default().domains_due(now));
+        // The node has no domains until it is up.
+        assert!(state(&[], now).domains_due(now));
+
+        let mut state = state(&["a.ts.net", "b.ts.net"], now);
+        assert!(!state.domains_due(now + TICK));
+        assert!(state.domains_due(now + DOMAINS_INTERVAL));
+
+        state.certs.insert("b.ts.net".to_string(), failed(now));
+        state.set_domains(vec!["a.ts.net".to_string()], now);
+        assert!(state.certs.is_empty());
+    }
+
+    #[test]
+    fn pending_certificates_skip_the_wait() {
+        // As when a handshake needs a certificate while the thread is refreshing, so not
+        // waiting for the notification.
+        let state = Mutex::new(state(&["web.tail1234.ts.net"], Instant::now()));
+        state.lock().unwrap().lookup(None);
+        let start = Instant::now();
+        assert!(wait_for_work(&state, &Condvar::new(), TICK));
+        assert!(start.elapsed() < TICK);
+        assert!(!state.lock().unwrap().pending);
+
+        // Otherwise it waits, until stopped.
+        let start = Instant::now();
+        assert!(wait_for_work(
+            &state,
+            &Condvar::new(),
+            Duration::from_millis(50)
+        ));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        state.lock().unwrap().stopped = true;
+        assert!(!wait_for_work(&state, &Condvar::new(), TICK));
+    }
+}
@@ -0,0 +1,81 @@
+//! gRPC on the tailnet with [`tonic`].
+//!
+//! Requires the `tonic` feature.
+//!
+//! [`Incoming`] serves gRPC services on a tailnet listener with
+//! [`serve_with_incoming`](::tonic::transport::server::Router::serve_with_incoming), and
+//! [`Connector`] connects channels to other tailnet nodes with
+//! [`connect_with_connector`](::tonic::transport::Endpoint::connect_with_connector).
+//!
+//! ## Example
+//!
+//! ```rust,no_run
+//! use tonic::{service::Routes, transport::{Endpoint, Server}};
+//! use tsnet::{tonic::{Connector, Incoming}, Network, ServerBuilder};
+//!
+//! # async fn run(routes: Routes) -> Result<(), Box<dyn std::error::Error>> {
+//! let ts = ServerBuilder::new().ephemeral().build()?;
+//!
+//! let ln = ts.listen_async(Network::Tcp, ":50051")?;
+//! tokio::spawn(
+//!     Server::builder()
+//!         .add_routes(routes)
+//!         .serve_with_incoming(Incoming::new(ln)),
+//! );
+//!
+//! let channel = Endpoint::from_static("http://other-node:50051")
+//!     .connect_with_connector(Connector::new(ts.clone()))
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use ::tonic::transport::server::Connected;
+use futures_core::Stream;
+use tokio::net::TcpStream;
+
+pub use crate::connector::Connector;
+use crate::{AsyncListener, Error, IncomingConn, PeerAddr};
+
+/// The connections accepted by an [`AsyncListener`], for serving them with tonic.
+///
+/// Each connection carries its [`PeerAddr`], which tonic makes available to services in the
+/// extensions of each request. The stream ends once the server of the listener is closed.
+#[derive(Debug)]
+pub struct Incoming {
+    listener: AsyncListener,
+}
+
+impl Incoming {
+    /// Serve the connections accepted by `listener`.
+    pub fn new(listener: AsyncListener) -> Self {
+        Incoming { listener }
+    }
+}
+
+impl Stream for Incoming {
+    type Item = io::Result<IncomingConn<TcpStream>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.listener.poll_accept(cx) {
+            Poll::Ready(Ok(conn)) => Poll::Ready(Some(Ok(conn))),
+            Poll::Ready(Err(Error::ServerClosed)) => Poll::Ready(None),
+            Poll::Ready(Err(err)) => Poll::Ready(Some(Err(err.into()))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl Connected for IncomingConn<TcpStream> {
+    type ConnectInfo = PeerAddr;
+
+    fn connect_info(&self) -> PeerAddr {
+        self.peer()
+    }
+}
@@ -3,52 +3,205 @@
 
 #include "tailscale.h"
 #include <sys/socket.h>
+#include <errno.h>
+#include <stdint.h>
 #include <stdio.h>
+#include <string.h>
 #include <unistd.h>
 
 // Functions exported by Go.
 extern int TsnetNewServer();
-extern int TsnetStart(int sd);
-extern int TsnetUp(int sd);
+extern int TsnetStart(int sd, int* errOut);
+extern int TsnetUp(int sd, int* errOut);
+extern int TsnetUpTimeout(int sd, int timeoutMs, int* errOut);
 extern int TsnetClose(int sd);
+extern int TsnetShutdown(int sd, int timeoutMs, int* listenersOut, int* drainedOut, int* forcedOut, int* loggedOutOut);
 extern int TsnetErrmsg(int sd, char* buf, size_t buflen);
-extern int TsnetDial(int sd, char* net, char* addr, int* connOut);
-extern int TsnetSetDir(int sd, char* str);
-extern int TsnetSetHostname(int sd, char* str);
-extern int TsnetSetAuthKey(int sd, char* str);
-extern int TsnetSetControlURL(int sd, char* str);
-extern int TsnetSetEphemeral(int sd, int ephemeral);
-extern int TsnetSetLogFD(int sd, int fd);
-extern int TsnetListen(int sd, char* net, char* addr, int* listenerOut);
-extern int TsnetLoopback(int sd, char* addrOut, size_t addrLen, char* proxyOut, char* localOut);
+extern int TsnetErrcode(int sd);
+extern int TsnetDialStart(int sd, char* net, char* addr, int* opOut, int* errOut);
+extern int TsnetUpStart(int sd, int* opOut, int* errOut);
+extern int TsnetErrorCode(int ed);
+extern int TsnetErrorMsg(int ed, char* buf, size_t buflen);
+extern void TsnetErrorFree(int ed);
+extern int TsnetDial(int sd, char* net, char* addr, int* connOut, int* errOut);
+extern int TsnetSetDir(int sd, char* str, int* errOut);
+extern int TsnetSetHostname(int sd, char* str, int* errOut);
+extern int TsnetSetAuthKey(int sd, char* str, int* errOut);
+extern int TsnetSetControlURL(int sd, char* str, int* errOut);
+extern int TsnetSetEphemeral(int sd, int ephemeral, int* errOut);
+extern int TsnetSetLogFD(int sd, int fd, int* errOut);
+extern int TsnetSetStateStore(int sd, tailscale_state_read_fn read, tailscale_state_write_fn write, void* ctx, int* errOut);
+extern int TsnetSetStateInMemory(int sd, int* errOut);
+extern int TsnetGetState(int sd, char* key, char* buf, size_t buflen, size_t* lenOut, int* errOut);
+extern int TsnetSetState(int sd, char* key, char* value, size_t valueLen, int* errOut);
+extern int TsnetListen(int sd, char* net, char* addr, int* listenerOut, int* errOut);
+extern int TsnetGetIps(int sd, char* buf, size_t buflen, int* errOut);
+extern int TsnetUpStatus(int sd, char* buf, size_t buflen, int* errOut);
+extern int TsnetWhoIs(int sd, char* addr, char* buf, size_t buflen, int* errOut);
+extern int TsnetListenTLS(int sd, char* net, char* addr, int* listenerOut, int* errOut);
+extern int TsnetListenFunnel(int sd, char* net, char* addr, int funnelOnly, int* listenerOut, int* errOut);
+extern int TsnetCertDomains(int sd, char* buf, size_t buflen, int* errOut);
+extern int TsnetCertPair(int sd, char* domain, char* buf, size_t buflen, int* errOut);
+extern int TsnetListenPacket(int sd, char* net, char* addr, int* packetOut, int* errOut);
+extern int TsnetLoopback(int sd, char* addrOut, size_t addrLen, char* proxyOut, char* localOut, int* errOut);
 
 tailscale tailscale_new() {
 	return TsnetNewServer();
 }
 
 int tailscale_start(tailscale sd) {
-	return TsnetStart(sd);
+	return tailscale_start_err(sd, NULL);
+}
+int tailscale_start_err(tailscale sd, tailscale_error* err_out) {
+	return TsnetStart(sd, (int*)err_out);
 }
 
 int tailscale_up(tailscale sd) {
-	return TsnetUp(sd);
+	return tailscale_up_err(sd, NULL);
+}
+int tailscale_up_err(tailscale sd, tailscale_error* err_out) {
+	return TsnetUp(sd, (int*)err_out);
+}
+
+int tailscale_up_timeout(tailscale sd, int timeout_ms) {
+	return tailscale_up_timeout_err(sd, timeout_ms, NULL);
+}
+int tailscale_up_timeout_err(tailscale sd, int timeout_ms, tailscale_error* err_out) {
+	return TsnetUpTimeout(sd, timeout_ms, (int*)err_out);
 }
 
 int tailscale_close(tailscale sd) {
 	return TsnetClose(sd);
 }
 
+int tailscale_shutdown(tailscale sd, int timeout_ms, tailscale_shutdown_report* report_out) {
+	tailscale_shutdown_report report = {0};
+	int ret = TsnetShutdown(sd, timeout_ms, &report.listeners_closed, &report.conns_drained, &report.conns_forced, &report.logged_out);
+	if (report_out != NULL) {
+		*report_out = report;
+	}
+	return ret;
+}
+
 int tailscale_dial(tailscale sd, const char* network, const char* addr, tailscale_conn* conn_out) {
-	return TsnetDial(sd, (char*)network, (char*)addr, (int*)conn_out);
+	return tailscale_dial_err(sd, network, addr, conn_out, NULL);
+}
+int tailscale_dial_err(tailscale sd, const char* network, const char* addr, tailscale_conn* conn_out, tailscale_error* err_out) {
+	return TsnetDial(sd, (char*)network, (char*)addr, (int*)conn_out, (int*)err_out);
+}
+
+int tailscale_dial_start(tailscale sd, const char* network, const char* addr, tailscale_op* op_out, tailscale_error* err_out) {
+	return TsnetDialStart(sd, (char*)network, (char*)addr, (int*)op_out, (int*)err_out);
+}
+
+int tailscale_up_start(tailscale sd, tailscale_op* op_out, tailscale_error* err_out) {
+	return TsnetUpStart(sd, (int*)op_out, (int*)err_out);
+}
+
+int tailscale_op_result(tailscale_op op, tailscale_conn* conn_out, int* code_out, char* errmsg, size_t errmsglen) {
+	struct msghdr msg = {0};
+
+	// The result is the error code followed by the error message.
+	char mbuf[sizeof(int32_t) + TAILSCALE_OP_ERRMSG_LEN];
+	struct iovec io = { .iov_base = mbuf, .iov_len = sizeof(mbuf) };
+	msg.msg_iov = &io;
+	msg.msg_iovlen = 1;
+
+	char cbuf[256];
+	msg.msg_control = cbuf;
+	msg.msg_controllen = sizeof(cbuf);
+
+	ssize_t n = recvmsg(op, &msg, 0);
+	if (n == -1) {
+		return errno;
+	}
+	if (n < (ssize_t)sizeof(int32_t)) {
+		return ESHUTDOWN;
+	}
+
+	int32_t code;
+	memcpy(&code, mbuf, sizeof(code));
+	if (code_out != NULL) {
+		*code_out = code;
+	}
+
+	int fd = -1;
+	struct cmsghdr* cmsg = CMSG_FIRSTHDR(&msg);
+	if (cmsg != NULL) {
+		memcpy(&fd, CMSG_DATA(cmsg), sizeof(fd));
+	}
+	if (conn_out != NULL) {
+		*conn_out = fd;
+	} else if (fd != -1) {
+		close(fd);
+	}
+
+	if (errmsg != NULL && errmsglen > 0) {
+		size_t len = (size_t)n - sizeof(int32_t);
+		if (len > errmsglen - 1) {
+			len = errmsglen - 1;
+		}
+		memcpy(errmsg, mbuf + sizeof(int32_t), len);
+		errmsg[len] = '\0';
+	}
+	return code == TAILSCALE_ERR_NONE ? 0 : -1;
 }
 
 int tailscale_listen(tailscale sd, const char* network, const char* addr, tailscale_listener* listener_out) {
-	return TsnetListen(sd, (char*)network, (char*)addr, (int*)listener_out);
+	return tailscale_listen_err(sd, network, addr, listener_out, NULL);
+}
+int tailscale_listen_err(tailscale sd, const char* network, const char* addr, tailscale_listener* listener_out, tailscale_error* err_out) {
+	return TsnetListen(sd, (char*)network, (char*)addr, (int*)listener_out, (int*)err_out);
+}
+
+int tailscale_listen_tls(tailscale sd, const char* network, const char* addr, tailscale_listener* listener_out) {
+	return tailscale_listen_tls_err(sd, network, addr, listener_out, NULL);
+}
+int tailscale_listen_tls_err(tailscale sd, const char* network, const char* addr, tailscale_listener* listener_out, tailscale_error* err_out) {
+	return TsnetListenTLS(sd, (char*)network, (char*)addr, (int*)listener_out, (int*)err_out);
+}
+
+int tailscale_listen_funnel(tailscale sd, const char* network, const char* addr, int funnel_only, tailscale_listener* listener_out) {
+	return tailscale_listen_funnel_err(sd, network, addr, funnel_only, listener_out, NULL);
+}
+int tailscale_listen_funnel_err(tailscale sd, const char* network, const char* addr, int funnel_only, tailscale_listener* listener_out, tailscale_error* err_out) {
+	return TsnetListenFunnel(sd, (char*)network, (char*)addr, funnel_only, (int*)listener_out, (int*)err_out);
+}
+
+int tailscale_cert_domains(tailscale sd, char* buf, size_t buflen) {
+	return tailscale_cert_domains_err(sd, buf, buflen, NULL);
+}
+int tailscale_cert_domains_err(tailscale sd, char* buf, size_t buflen, tailscale_error* err_out) {
+	return TsnetCertDomains(sd, buf, buflen, (int*)err_out);
+}
+
+int tailscale_cert_pair(tailscale sd, const char* domain, char* buf, size_t buflen) {
+	return tailscale_cert_pair_err(sd, domain, buf, buflen, NULL);
+}
+int tailscale_cert_pair_err(tailscale sd, const char* domain, char* buf, size_t buflen, tailscale_error* err_out) {
+	return TsnetCertPair(sd, (char*)domain, buf, buflen, (int*)err_out);
+}
+
+int tailscale_listen_packet(tailscale sd, const char* network, const char* addr, tailscale_packet* packet_out) {
+	return tailscale_listen_packet_err(sd, network, addr, packet_out, NULL);
+}
+int tailscale_listen_packet_err(tailscale sd, const char* network, const char* addr, tailscale_packet* packet_out, tailscale_error* err_out) {
+	return TsnetListenPacket(sd, (char*)network, (char*)addr, (int*)packet_out, (int*)err_out);
 }
 
 int tailscale_accept(tailscale_listener ld, tailscale_conn* conn_out) {
+	return tailscale_accept_addr(ld, conn_out, NULL, 0);
+}
+
+int tailscale_accept_addr(tailscale_listener ld, tailscale_conn* conn_out, char* remote_addr_out, size_t addrlen) {
+	return tailscale_accept_info(ld, conn_out, remote_addr_out, addrlen, NULL);
+}
+
+int tailscale_accept_info(tailscale_listener ld, tailscale_conn* conn_out, char* remote_addr_out, size_t addrlen, int* flags_out) {
 	struct msghdr msg = {0};
 
+	// The data sent along with the connection fd is a flags byte
+	// followed by the remote address.
 	char mbuf[256];
 	struct iovec io = { .iov_base = mbuf, .iov_len = sizeof(mbuf) };
 	msg.msg_iov = &io;
@@ -58,41 +211,141 @@ int tailscale_accept(tailscale_listener ld, tailscale_conn* conn_out) {
 	msg.msg_control = cbuf;
 	msg.msg_controllen = sizeof(cbuf);
 
-	if (recvmsg(ld, &msg, 0) == -1) {
+	ssize_t n = recvmsg(ld, &msg, 0);
+	if (n == -1) {
 		return -1;
 	}
 
+	// The Go side hung up: the listener or its server was closed.
 	struct cmsghdr* cmsg = CMSG_FIRSTHDR(&msg);
+	if (n == 0 || cmsg == NULL) {
+		return ESHUTDOWN;
+	}
 	unsigned char* data = CMSG_DATA(cmsg);
 
 	int fd = *(int*)data;
 	*conn_out = fd;
+
+	if (flags_out != NULL) {
+		*flags_out = n > 0 ? (unsigned char)mbuf[0] : 0;
+	}
+	if (remote_addr_out != NULL && addrlen > 0) {
+		size_t len = n > 1 ? (size_t)n - 1 : 0;
+		if (len > addrlen - 1) {
+			len = addrlen - 1;
+		}
+		memcpy(remote_addr_out, mbuf + 1, len);
+		remote_addr_out[len] = '\0';
+	}
 	return 0;
 }
 
 int tailscale_set_dir(tailscale sd, const char* dir) {
-	return TsnetSetDir(sd, (char*)dir);
+	return tailscale_set_dir_err(sd, dir, NULL);
+}
+int tailscale_set_dir_err(tailscale sd, const char* dir, tailscale_error* err_out) {
+	return TsnetSetDir(sd, (char*)dir, (int*)err_out);
 }
 int tailscale_set_hostname(tailscale sd, const char* hostname) {
-	return TsnetSetHostname(sd, (char*)hostname);
+	return tailscale_set_hostname_err(sd, hostname, NULL);
+}
+int tailscale_set_hostname_err(tailscale sd, const char* hostname, tailscale_error* err_out) {
+	return TsnetSetHostname(sd, (char*)hostname, (int*)err_out);
 }
 int tailscale_set_authkey(tailscale sd, const char* authkey) {
-	return TsnetSetAuthKey(sd, (char*)authkey);
+	return tailscale_set_authkey_err(sd, authkey, NULL);
+}
+int tailscale_set_authkey_err(tailscale sd, const char* authkey, tailscale_error* err_out) {
+	return TsnetSetAuthKey(sd, (char*)authkey, (int*)err_out);
 }
 int tailscale_set_control_url(tailscale sd, const char* control_url) {
-	return TsnetSetControlURL(sd, (char*)control_url);
+	return tailscale_set_control_url_err(sd, control_url, NULL);
+}
+int tailscale_set_control_url_err(tailscale sd, const char* control_url, tailscale_error* err_out) {
+	return TsnetSetControlURL(sd, (char*)control_url, (int*)err_out);
 }
 int tailscale_set_ephemeral(tailscale sd, int ephemeral) {
-	return TsnetSetEphemeral(sd, ephemeral);
+	return tailscale_set_ephemeral_err(sd, ephemeral, NULL);
+}
+int tailscale_set_ephemeral_err(tailscale sd, int ephemeral, tailscale_error* err_out) {
+	return TsnetSetEphemeral(sd, ephemeral, (int*)err_out);
 }
 int tailscale_set_logfd(tailscale sd, int fd) {
-	return TsnetSetLogFD(sd, fd);
+	return tailscale_set_logfd_err(sd, fd, NULL);
+}
+int tailscale_set_logfd_err(tailscale sd, int fd, tailscale_error* err_out) {
+	return TsnetSetLogFD(sd, fd, (int*)err_out);
+}
+
+int tailscale_set_state_store(tailscale sd, tailscale_state_read_fn read, tailscale_state_write_fn write, void* ctx) {
+	return tailscale_set_state_store_err(sd, read, write, ctx, NULL);
+}
+int tailscale_set_state_store_err(tailscale sd, tailscale_state_read_fn read, tailscale_state_write_fn write, void* ctx, tailscale_error* err_out) {
+	return TsnetSetStateStore(sd, read, write, ctx, (int*)err_out);
+}
+int tailscale_set_state_in_memory(tailscale sd) {
+	return tailscale_set_state_in_memory_err(sd, NULL);
+}
+int tailscale_set_state_in_memory_err(tailscale sd, tailscale_error* err_out) {
+	return TsnetSetStateInMemory(sd, (int*)err_out);
+}
+int tailscale_get_state(tailscale sd, const char* key, char* buf, size_t buflen, size_t* len_out) {
+	return tailscale_get_state_err(sd, key, buf, buflen, len_out, NULL);
+}
+int tailscale_get_state_err(tailscale sd, const char* key, char* buf, size_t buflen, size_t* len_out, tailscale_error* err_out) {
+	return TsnetGetState(sd, (char*)key, buf, buflen, len_out, (int*)err_out);
+}
+int tailscale_set_state(tailscale sd, const char* key, const char* value, size_t len) {
+	return tailscale_set_state_err(sd, key, value, len, NULL);
+}
+int tailscale_set_state_err(tailscale sd, const char* key, const char* value, size_t len, tailscale_error* err_out) {
+	return TsnetSetState(sd, (char*)key, (char*)value, len, (int*)err_out);
+}
+
+int tailscale_up_status(tailscale sd, char* buf, size_t buflen) {
+	return tailscale_up_status_err(sd, buf, buflen, NULL);
+}
+int tailscale_up_status_err(tailscale sd, char* buf, size_t buflen, tailscale_error* err_out) {
+	return TsnetUpStatus(sd, buf, buflen, (int*)err_out);
+}
+
+int tailscale_getips(tailscale sd, char* buf, size_t buflen) {
+	return tailscale_getips_err(sd, buf, buflen, NULL);
+}
+int tailscale_getips_err(tailscale sd, char* buf, size_t buflen, tailscale_error* err_out) {
+	return TsnetGetIps(sd, buf, buflen, (int*)err_out);
+}
+
+int tailscale_whois(tailscale sd, const char* addr, char* buf, size_t buflen) {
+	return tailscale_whois_err(sd, addr, buf, buflen, NULL);
+}
+int tailscale_whois_err(tailscale sd, const char* addr, char* buf, size_t buflen, tailscale_error* err_out) {
+	return TsnetWhoIs(sd, (char*)addr, buf, buflen, (int*)err_out);
 }
 
 int tailscale_loopback(tailscale sd, char* addr_out, size_t addrlen, char* proxy_cred_out, char* local_api_cred_out) {
-	return TsnetLoopback(sd, addr_out, addrlen, proxy_cred_out, local_api_cred_out);
+	return tailscale_loopback_err(sd, addr_out, addrlen, proxy_cred_out, local_api_cred_out, NULL);
+}
+int tailscale_loopback_err(tailscale sd, char* addr_out, size_t addrlen, char* proxy_cred_out, char* local_api_cred_out, tailscale_error* err_out) {
+	return TsnetLoopback(sd, addr_out, addrlen, proxy_cred_out, local_api_cred_out, (int*)err_out);
 }
 
 int tailscale_errmsg(tailscale sd, char* buf, size_t buflen) {
 	return TsnetErrmsg(sd, buf, buflen);
 }
+
+int tailscale_errcode(tailscale sd) {
+	return TsnetErrcode(sd);
+}
+
+int tailscale_error_code(tailscale_error err) {
+	return TsnetErrorCode(err);
+}
+
+int tailscale_error_msg(tailscale_error err, char* buf, size_t buflen) {
+	return TsnetErrorMsg(err, buf, buflen);
+}
+
+void tailscale_error_free(tailscale_error err) {
+	TsnetErrorFree(err);
+}
@@ -4,20 +4,48 @@
 // A Go c-archive of the tsnet package. See tailscale.h for details.
 package main
 
-//#include "errno.h"
+/*
+#include "errno.h"
+#include "stddef.h"
+
+// As declared in tailscale.h.
+typedef int (*tailscale_state_read_fn)(void* ctx, const char* key, char* buf, size_t buflen, size_t* len_out);
+typedef int (*tailscale_state_write_fn)(void* ctx, const char* key, const char* value, size_t len);
+
+// Go can't call C function pointers itself.
+static inline int call_state_read(tailscale_state_read_fn f, void* ctx, const char* key, char* buf, size_t buflen, size_t* len_out) {
+	return f(ctx, key, buf, buflen, len_out);
+}
+static inline int call_state_write(tailscale_state_write_fn f, void* ctx, const char* key, const char* value, size_t len) {
+	return f(ctx, key, value, len);
+}
+*/
 import "C"
 
 import (
 	"context"
+	"crypto/tls"
+	"encoding/binary"
+	"encoding/json"
+	"errors"
 	"fmt"
 	"io"
 	"net"
+	"net/netip"
 	"os"
+	"path/filepath"
+	"strings"
 	"sync"
+	"sync/atomic"
 	"syscall"
+	"time"
 	"unsafe"
 
 	"tailscale.com/hostinfo"
+	"tailscale.com/ipn"
+	"tailscale.com/ipn/ipnstate"
+	"tailscale.com/ipn/store"
+	"tailscale.com/ipn/store/mem"
 	"tailscale.com/tsnet"
 	"tailscale.com/types/logger"
 )
@@ -33,16 +61,93 @@ var servers struct {
 
 type server struct {
 	s       *tsnet.Server
-	lastErr string
+	ctx     context.Context // canceled when the server is closed
+	cancel  context.CancelFunc
+	started atomic.Bool
+
+	startOnce sync.Once // see markStarted
+	startErr  error
+	inMemory  bool   // see TsnetSetStateInMemory
+	tmpDir    string // removed once closed, see prepareDir
+
+	mu       sync.Mutex // guards the fields below
+	lastErr  string
+	lastCode C.int            // the errCode* classifying lastErr
+	upStatus *ipnstate.Status // as returned by the last successful Up
+}
+
+// up waits for the server to be usable, as tsnet.Server.Up does, and keeps
+// the status it returns for tailscale_up_status.
+func (s *server) up(ctx context.Context) error {
+	if err := s.markStarted(); err != nil {
+		return err
+	}
+	st, err := s.s.Up(ctx)
+	if err != nil {
+		return &codedError{err: err, code: s.upErrCode(err)}
+	}
+	s.mu.Lock()
+	defer s.mu.Unlock()
+	s.upStatus = st
+	return nil
+}
+
+// start starts the server if it is not running yet, as tsnet does
+// implicitly on first use, and records that it was started.
+func (s *server) start() error {
+	if err := s.markStarted(); err != nil {
+		return err
+	}
+	return s.s.Start()
+}
+
+// markStarted records that the server is being started, after which it
+// can't be configured anymore, and prepares its directory the first time.
+func (s *server) markStarted() error {
+	s.startOnce.Do(func() {
+		s.started.Store(true)
+		s.startErr = s.prepareDir()
+	})
+	return s.startErr
+}
+
+// prepareDir gives a server with in-memory state a private temporary
+// directory, unless one was set. tsnet can't run without a directory, as it
+// keeps its log configuration and log buffer there, and the default one is
+// shared by every run of the program. The directory is removed by close.
+func (s *server) prepareDir() error {
+	if !s.inMemory || s.s.Dir != "" {
+		return nil
+	}
+	dir, err := os.MkdirTemp("", "tsnet-")
+	if err != nil {
+		return err
+	}
+	s.s.Dir = dir
+	s.tmpDir = dir
+	return nil
+}
+
+// close closes the tsnet.Server, and removes its temporary directory, if any.
+func (s *server) close() error {
+	err := s.s.Close()
+	if s.tmpDir != "" {
+		os.RemoveAll(s.tmpDir)
+	}
+	return err
 }
 
+// errUnknownServer is the error for server descriptors that don't refer
+// to a server, either never allocated or already closed.
+var errUnknownServer = errors.New("tsnetc: unknown server descriptor")
+
 func getServer(sd C.int) (*server, error) {
 	servers.mu.Lock()
 	defer servers.mu.Unlock()
 
 	s := servers.m[sd]
 	if s == nil {
-		return nil, fmt.Errorf("tsnetc: unknown server descriptors %d (of %d servers)", sd, len(servers.m))
+		return nil, fmt.Errorf("%w %d (of %d servers)", errUnknownServer, sd, len(servers.m))
 	}
 	return s, nil
 }
@@ -59,6 +164,26 @@ type listener struct {
 	fd int // go side fd of socketpair sent to C
 }
 
+// packetConns tracks all the tailscale_packet objects allocated via tsnet_listen_packet.
+var packetConns struct {
+	mu sync.Mutex
+	m  map[C.int]*packetConn
+}
+
+type packetConn struct {
+	s  *server
+	pc net.PacketConn
+	fd int // go side fd of socketpair sent to C
+}
+
+// packetHeaderLen is the length of the peer address header prefixed to
+// each datagram going through a tailscale_packet: a 16 byte IP address
+// (IPv4-mapped for IPv4) followed by a big-endian uint16 port.
+const packetHeaderLen = 18
+
+// maxPacketLen is the largest datagram payload passed through a tailscale_packet.
+const maxPacketLen = 1<<16 - 1
+
 // conns tracks all the pipe(2)s allocated via tsnet_dial.
 var conns struct {
 	mu sync.Mutex
@@ -71,15 +196,192 @@ type conn struct {
 	r *os.File // r is the local socket to the C client
 }
 
-func (s *server) recErr(err error) C.int {
+// Error codes classifying the last error of a server, as returned by
+// tailscale_errcode. Keep in sync with the TAILSCALE_ERR_* values in tailscale.h.
+const (
+	errCodeNone               = 0
+	errCodeOther              = 1
+	errCodeNeedsLogin         = 2
+	errCodeAuthKeyInvalid     = 3
+	errCodeControlUnreachable = 4
+	errCodeConnRefused        = 5
+	errCodeTimeout            = 6
+	errCodeAddrInUse          = 7
+	errCodeUnknownHandle      = 8
+	errCodeServerClosed       = 9
+)
+
+// errs tracks the tailscale_error objects handed out to C by the
+// functions taking an err_out.
+var errs struct {
+	mu   sync.Mutex
+	next C.int
+	m    map[C.int]*callErr
+}
+
+type callErr struct {
+	msg  string
+	code C.int
+}
+
+// recErr records err as the last error of s, and returns the C return
+// value for it. If errOut is not nil and err is not nil, a tailscale_error
+// for err is also allocated and written to it, which is the only way to
+// get at the error that's safe with concurrent calls on s.
+//
+// It is safe to call with a nil s, as returned by getServer for unknown
+// descriptors: tailscale_errmsg and tailscale_errcode then report the
+// unknown descriptor themselves.
+func (s *server) recErr(err error, errOut *C.int) C.int {
+	return s.recErrCode(err, s.errCode(err), errOut)
+}
+
+// recErrCode is like recErr, with the error code already known.
+func (s *server) recErrCode(err error, code C.int, errOut *C.int) C.int {
+	if err != nil && errOut != nil {
+		*errOut = newCallErr(err.Error(), code)
+	}
+	if s == nil {
+		return -1
+	}
+
+	s.mu.Lock()
+	defer s.mu.Unlock()
 	if err == nil {
 		s.lastErr = ""
+		s.lastCode = errCodeNone
 		return 0
 	}
 	s.lastErr = err.Error()
+	s.lastCode = code
 	return -1
 }
 
+// newCallErr allocates a tailscale_error.
+func newCallErr(msg string, code C.int) C.int {
+	errs.mu.Lock()
+	defer errs.mu.Unlock()
+
+	if errs.m == nil {
+		errs.m = map[C.int]*callErr{}
+	}
+	if errs.next == 0 {
+		errs.next = 43<<16 + 1
+	}
+	ed := errs.next
+	errs.next++
+	errs.m[ed] = &callErr{msg: msg, code: code}
+	return ed
+}
+
+func getCallErr(ed C.int) *callErr {
+	errs.mu.Lock()
+	defer errs.mu.Unlock()
+	return errs.m[ed]
+}
+
+// codedError is an error classified where it happened, with more context
+// than errCode has.
+type codedError struct {
+	err  error
+	code C.int
+}
+
+func (e *codedError) Error() string { return e.err.Error() }
+func (e *codedError) Unwrap() error { return e.err }
+
+// errCode classifies err for tailscale_errcode.
+func (s *server) errCode(err error) C.int {
+	if err == nil {
+		return errCodeNone
+	}
+	var coded *codedError
+	if errors.As(err, &coded) {
+		return coded.code
+	}
+	if errors.Is(err, errUnknownServer) {
+		return errCodeUnknownHandle
+	}
+	if s != nil && s.ctx.Err() != nil && (errors.Is(err, context.Canceled) || errors.Is(err, net.ErrClosed)) {
+		return errCodeServerClosed
+	}
+
+	var netErr net.Error
+	switch {
+	case errors.Is(err, context.DeadlineExceeded), errors.Is(err, os.ErrDeadlineExceeded),
+		errors.Is(err, syscall.ETIMEDOUT):
+		return errCodeTimeout
+	case errors.As(err, &netErr) && netErr.Timeout():
+		return errCodeTimeout
+	case errors.Is(err, syscall.ECONNREFUSED):
+		return errCodeConnRefused
+	case errors.Is(err, syscall.EADDRINUSE):
+		return errCodeAddrInUse
+	}
+	return msgErrCode(err.Error())
+}
+
+// upBackendErrPrefix prefixes the errors of the control client, as relayed
+// by tsnet.Server.Up.
+const upBackendErrPrefix = "tsnet.Up: backend: "
+
+// msgErrCode classifies the errors that only come as a message: from the
+// userspace network stack, which doesn't use errnos, from tsnet itself, and
+// from the control server.
+func msgErrCode(msg string) C.int {
+	switch {
+	case strings.HasSuffix(msg, ": connection was refused"), // gVisor's tcpip.ErrConnectionRefused
+		strings.HasSuffix(msg, ": connection refused"):
+		return errCodeConnRefused
+	case strings.HasPrefix(msg, "tsnet: listener already open for "):
+		return errCodeAddrInUse
+	case strings.HasPrefix(msg, upBackendErrPrefix):
+		msg = strings.TrimPrefix(msg, upBackendErrPrefix)
+		// The control server rejecting the auth key.
+		if strings.HasPrefix(msg, "invalid key") || strings.Contains(msg, "key expired") {
+			return errCodeAuthKeyInvalid
+		}
+	}
+	return errCodeOther
+}
+
+// backendStateErrCode classifies what a node in backend state is waiting
+// for: interactive login, or reaching the control server at all. It returns
+// errCodeNone if it isn't stuck.
+func backendStateErrCode(state string) C.int {
+	switch state {
+	case ipn.NeedsLogin.String():
+		return errCodeNeedsLogin
+	case ipn.NoState.String():
+		// The node never heard back from the control server.
+		return errCodeControlUnreachable
+	}
+	return errCodeNone
+}
+
+// upErrCode classifies an error of tsnet.Server.Up, by the state of the
+// control client when the error wasn't more specific.
+func (s *server) upErrCode(err error) C.int {
+	code := s.errCode(err)
+	if (code != errCodeTimeout && code != errCodeOther) || errors.Is(err, context.Canceled) {
+		return code
+	}
+	lc, lcErr := s.s.LocalClient()
+	if lcErr != nil {
+		return code
+	}
+	ctx, cancel := context.WithTimeout(s.ctx, time.Second)
+	defer cancel()
+	st, stErr := lc.StatusWithoutPeers(ctx)
+	if stErr != nil {
+		return code
+	}
+	if stateCode := backendStateErrCode(st.BackendState); stateCode != errCodeNone {
+		return stateCode
+	}
+	return code
+}
+
 //export TsnetNewServer
 func TsnetNewServer() C.int {
 	servers.mu.Lock()
@@ -94,28 +396,45 @@ func TsnetNewServer() C.int {
 	}
 	sd := servers.next
 	servers.next++
-	s := &server{s: &tsnet.Server{}}
+	ctx, cancel := context.WithCancel(context.Background())
+	s := &server{s: &tsnet.Server{}, ctx: ctx, cancel: cancel}
 	servers.m[sd] = s
 	return (C.int)(sd)
 }
 
 //export TsnetStart
-func TsnetStart(sd C.int) C.int {
+func TsnetStart(sd C.int, errOut *C.int) C.int {
 	s, err := getServer(sd)
 	if err != nil {
-		return s.recErr(err)
+		return s.recErr(err, errOut)
 	}
-	return s.recErr(s.s.Start())
+	return s.recErr(s.start(), errOut)
 }
 
 //export TsnetUp
-func TsnetUp(sd C.int) C.int {
+func TsnetUp(sd C.int, errOut *C.int) C.int {
+	s, err := getServer(sd)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	err = s.up(s.ctx) // cancellation is via TsnetClose
+	return s.recErr(err, errOut)
+}
+
+//export TsnetUpTimeout
+func TsnetUpTimeout(sd C.int, timeoutMs C.int, errOut *C.int) C.int {
 	s, err := getServer(sd)
 	if err != nil {
-		return s.recErr(err)
+		return s.recErr(err, errOut)
+	}
+	ctx, cancel := context.WithTimeout(s.ctx, time.Duration(timeoutMs)*time.Millisecond)
+	defer cancel()
+	err = s.up(ctx)
+	if errors.Is(err, context.DeadlineExceeded) {
+		s.recErr(err, errOut)
+		return C.ETIMEDOUT
 	}
-	_, err = s.s.Up(context.Background()) // cancellation is via TsnetClose
-	return s.recErr(err)
+	return s.recErr(err, errOut)
 }
 
 //export TsnetClose
@@ -131,10 +450,144 @@ func TsnetClose(sd C.int) C.int {
 		return C.EBADF
 	}
 
-	// TODO: cancel Up
-	// TODO: close related listeners / conns.
-	if err := s.s.Close(); err != nil {
-		s.s.Logf("tailscale_close: failed with %v", err)
+	s.cancel()
+	s.closeListeners()
+	s.closeConns()
+	if err := s.close(); err != nil {
+		if s.s.Logf != nil {
+			s.s.Logf("tailscale_close: failed with %v", err)
+		}
+		return -1
+	}
+
+	return 0
+}
+
+// closeListeners tears down the listeners and packet conns handed out to C
+// for s, so that they don't outlive it. C sees them hang up: a blocked
+// tailscale_accept returns ESHUTDOWN and packet conns read EOF.
+//
+// The socketpairs are shut down rather than closed, as their goroutines
+// close them once they notice, and that's the only safe place to do so.
+//
+// It returns the number of listeners and packet conns closed.
+func (s *server) closeListeners() int {
+	var n int
+
+	listeners.mu.Lock()
+	for _, l := range listeners.m {
+		if l.s == s {
+			syscall.Shutdown(l.fd, syscall.SHUT_RDWR)
+			l.ln.Close()
+			n++
+		}
+	}
+	listeners.mu.Unlock()
+
+	packetConns.mu.Lock()
+	for _, pc := range packetConns.m {
+		if pc.s == s {
+			syscall.Shutdown(pc.fd, syscall.SHUT_RDWR)
+			pc.pc.Close()
+			n++
+		}
+	}
+	packetConns.mu.Unlock()
+
+	return n
+}
+
+// closeConns tears down the conns handed out to C for s, like
+// closeListeners. C reads EOF on them.
+//
+// It returns the number of conns closed.
+func (s *server) closeConns() int {
+	var n int
+
+	conns.mu.Lock()
+	for _, c := range conns.m {
+		if c.s == s.s {
+			syscall.Shutdown(int(c.r.Fd()), syscall.SHUT_RDWR)
+			c.c.Close()
+			n++
+		}
+	}
+	conns.mu.Unlock()
+
+	return n
+}
+
+// countConns returns the number of conns handed out to C for s which are
+// still open.
+func (s *server) countConns() int {
+	var n int
+
+	conns.mu.Lock()
+	for _, c := range conns.m {
+		if c.s == s.s {
+			n++
+		}
+	}
+	conns.mu.Unlock()
+
+	return n
+}
+
+// logoutTimeout bounds how long TsnetShutdown waits for an ephemeral node
+// to log out, on top of the drain timeout.
+const logoutTimeout = 5 * time.Second
+
+//export TsnetShutdown
+func TsnetShutdown(sd C.int, timeoutMs C.int, listenersOut, drainedOut, forcedOut, loggedOutOut *C.int) C.int {
+	servers.mu.Lock()
+	s := servers.m[sd]
+	if s != nil {
+		delete(servers.m, sd)
+	}
+	servers.mu.Unlock()
+
+	if s == nil {
+		return C.EBADF
+	}
+
+	deadline := time.Now().Add(time.Duration(timeoutMs) * time.Millisecond)
+
+	// Cancel any in-flight Up or Dial, and stop accepting.
+	s.cancel()
+	*listenersOut = C.int(s.closeListeners())
+
+	// Give the open conns until the deadline to finish.
+	open := s.countConns()
+	for n := open; n > 0 && time.Now().Before(deadline); n = s.countConns() {
+		time.Sleep(50 * time.Millisecond)
+	}
+	forced := s.closeConns()
+	if forced > open {
+		open = forced
+	}
+	*drainedOut = C.int(open - forced)
+	*forcedOut = C.int(forced)
+
+	// Ephemeral nodes are removed from the tailnet eventually, but logging
+	// out removes them right away.
+	*loggedOutOut = 0
+	if s.s.Ephemeral && s.started.Load() {
+		if lc, err := s.s.LocalClient(); err == nil {
+			ctx, cancel := context.WithTimeout(context.Background(), logoutTimeout)
+			err = lc.Logout(ctx)
+			cancel()
+			if err == nil {
+				*loggedOutOut = 1
+			} else if s.s.Logf != nil {
+				s.s.Logf("tailscale_shutdown: logout failed with %v", err)
+			}
+		}
+	}
+
+	if err := s.close(); err != nil {
+		if s.s.Logf != nil {
+			s.s.Logf("tailscale_shutdown: failed with %v", err)
+		}
 		return -1
 	}
 
@@ -158,27 +611,319 @@ func TsnetErrmsg(sd C.int, buf *C.char, buflen C.size_t) C.int {
 		out[0] = '\x00'
 		return C.EBADF
 	}
-	n := copy(out, s.lastErr)
-	if len(out) < len(s.lastErr)-1 {
-		out[len(out)-1] = '\x00' // always NUL-terminate
+	s.mu.Lock()
+	defer s.mu.Unlock()
+	return copyMsg(out, s.lastErr)
+}
+
+// copyMsg writes msg to out, NUL-terminated and truncated if need be.
+func copyMsg(out []byte, msg string) C.int {
+	n := copy(out[:len(out)-1], msg)
+	out[n] = '\x00' // always NUL-terminate
+	if n < len(msg) {
+		return C.ERANGE
+	}
+	return 0
+}
+
+//export TsnetErrcode
+func TsnetErrcode(sd C.int) C.int {
+	servers.mu.Lock()
+	s := servers.m[sd]
+	servers.mu.Unlock()
+
+	if s == nil {
+		return errCodeUnknownHandle
+	}
+	s.mu.Lock()
+	defer s.mu.Unlock()
+	return s.lastCode
+}
+
+//export TsnetErrorCode
+func TsnetErrorCode(ed C.int) C.int {
+	e := getCallErr(ed)
+	if e == nil {
+		return errCodeUnknownHandle
+	}
+	return e.code
+}
+
+//export TsnetErrorMsg
+func TsnetErrorMsg(ed C.int, buf *C.char, buflen C.size_t) C.int {
+	if buf == nil {
+		panic("error_msg passed nil buf")
+	} else if buflen == 0 {
+		panic("error_msg passed buflen of 0")
+	}
+
+	out := unsafe.Slice((*byte)(unsafe.Pointer(buf)), buflen)
+	e := getCallErr(ed)
+	if e == nil {
+		out[0] = '\x00'
+		return C.EBADF
+	}
+	return copyMsg(out, e.msg)
+}
+
+//export TsnetErrorFree
+func TsnetErrorFree(ed C.int) {
+	errs.mu.Lock()
+	defer errs.mu.Unlock()
+	delete(errs.m, ed)
+}
+
+//export TsnetGetIps
+func TsnetGetIps(sd C.int, buf *C.char, buflen C.size_t, errOut *C.int) C.int {
+	if buf == nil {
+		panic("getips passed nil buf")
+	} else if buflen == 0 {
+		panic("getips passed buflen of 0")
+	}
+
+	out := unsafe.Slice((*byte)(unsafe.Pointer(buf)), buflen)
+	out[0] = '\x00'
+
+	s, err := getServer(sd)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	if err := s.start(); err != nil {
+		return s.recErr(err, errOut)
+	}
+
+	var ips []string
+	ip4, ip6 := s.s.TailscaleIPs()
+	if ip4.IsValid() {
+		ips = append(ips, ip4.String())
+	}
+	if ip6.IsValid() {
+		ips = append(ips, ip6.String())
+	}
+	joined := strings.Join(ips, ",")
+	if len(joined)+1 > len(out) {
+		return s.recErr(fmt.Errorf("libtailscale: ips of %d bytes are too long for buflen %d", len(joined), buflen), errOut)
+	}
+	n := copy(out, joined)
+	out[n] = '\x00'
+	return 0
+}
+
+//export TsnetUpStatus
+func TsnetUpStatus(sd C.int, buf *C.char, buflen C.size_t, errOut *C.int) C.int {
+	if buf == nil {
+		panic("up_status passed nil buf")
+	} else if buflen == 0 {
+		panic("up_status passed buflen of 0")
+	}
+
+	out := unsafe.Slice((*byte)(unsafe.Pointer(buf)), buflen)
+	out[0] = '\x00'
+
+	s, err := getServer(sd)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	s.mu.Lock()
+	st := s.upStatus
+	s.mu.Unlock()
+	if st == nil {
+		return s.recErr(errors.New("libtailscale: server is not up yet"), errOut)
+	}
+	b, err := json.Marshal(st)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	if len(b)+1 > len(out) {
+		s.recErr(fmt.Errorf("libtailscale: up status of %d bytes is too long for buflen %d", len(b), buflen), errOut)
 		return C.ERANGE
 	}
+	n := copy(out, b)
+	out[n] = '\x00'
+	return 0
+}
+
+//export TsnetCertDomains
+func TsnetCertDomains(sd C.int, buf *C.char, buflen C.size_t, errOut *C.int) C.int {
+	if buf == nil {
+		panic("cert_domains passed nil buf")
+	} else if buflen == 0 {
+		panic("cert_domains passed buflen of 0")
+	}
+
+	out := unsafe.Slice((*byte)(unsafe.Pointer(buf)), buflen)
+	out[0] = '\x00'
+
+	s, err := getServer(sd)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	if err := s.start(); err != nil {
+		return s.recErr(err, errOut)
+	}
+	joined := strings.Join(s.s.CertDomains(), ",")
+	if len(joined)+1 > len(out) {
+		s.recErr(fmt.Errorf("libtailscale: cert domains of %d bytes are too long for buflen %d", len(joined), buflen), errOut)
+		return C.ERANGE
+	}
+	n := copy(out, joined)
+	out[n] = '\x00'
+	return 0
+}
+
+//export TsnetCertPair
+func TsnetCertPair(sd C.int, domain *C.char, buf *C.char, buflen C.size_t, errOut *C.int) C.int {
+	if buf == nil {
+		panic("cert_pair passed nil buf")
+	} else if buflen == 0 {
+		panic("cert_pair passed buflen of 0")
+	}
+
+	out := unsafe.Slice((*byte)(unsafe.Pointer(buf)), buflen)
+	out[0] = '\x00'
+
+	s, err := getServer(sd)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	if err := s.start(); err != nil {
+		return s.recErr(err, errOut)
+	}
+	lc, err := s.s.LocalClient()
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	certPEM, keyPEM, err := lc.CertPair(s.ctx, C.GoString(domain))
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	if len(keyPEM)+len(certPEM)+1 > len(out) {
+		s.recErr(fmt.Errorf("libtailscale: cert pair of %d bytes is too long for buflen %d", len(keyPEM)+len(certPEM), buflen), errOut)
+		return C.ERANGE
+	}
+	n := copy(out, keyPEM)
+	n += copy(out[n:], certPEM)
+	out[n] = '\x00'
+	return 0
+}
+
+//export TsnetWhoIs
+func TsnetWhoIs(sd C.int, addr *C.char, buf *C.char, buflen C.size_t, errOut *C.int) C.int {
+	if buf == nil {
+		panic("whois passed nil buf")
+	} else if buflen == 0 {
+		panic("whois passed buflen of 0")
+	}
+
+	out := unsafe.Slice((*byte)(unsafe.Pointer(buf)), buflen)
+	out[0] = '\x00'
+
+	s, err := getServer(sd)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	if err := s.start(); err != nil {
+		return s.recErr(err, errOut)
+	}
+	lc, err := s.s.LocalClient()
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	who, err := lc.WhoIs(s.ctx, C.GoString(addr))
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	b, err := json.Marshal(who)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	if len(b)+1 > len(out) {
+		s.recErr(fmt.Errorf("libtailscale: whois response of %d bytes is too long for buflen %d", len(b), buflen), errOut)
+		return C.ERANGE
+	}
+	n := copy(out, b)
 	out[n] = '\x00'
 	return 0
 }
 
 //export TsnetListen
-func TsnetListen(sd C.int, network, addr *C.char, listenerOut *C.int) C.int {
+func TsnetListen(sd C.int, network, addr *C.char, listenerOut, errOut *C.int) C.int {
 	s, err := getServer(sd)
 	if err != nil {
-		return s.recErr(err)
+		return s.recErr(err, errOut)
+	}
+	if err := s.start(); err != nil {
+		return s.recErr(err, errOut)
 	}
 
 	ln, err := s.s.Listen(C.GoString(network), C.GoString(addr))
 	if err != nil {
-		return s.recErr(err)
+		return s.recErr(err, errOut)
+	}
+	return listen(s, ln, listenerOut, errOut)
+}
+
+//export TsnetListenTLS
+func TsnetListenTLS(sd C.int, network, addr *C.char, listenerOut, errOut *C.int) C.int {
+	s, err := getServer(sd)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	if err := s.start(); err != nil {
+		return s.recErr(err, errOut)
+	}
+
+	ln, err := s.s.ListenTLS(C.GoString(network), C.GoString(addr))
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	return listen(s, ln, listenerOut, errOut)
+}
+
+//export TsnetListenFunnel
+func TsnetListenFunnel(sd C.int, network, addr *C.char, funnelOnly C.int, listenerOut, errOut *C.int) C.int {
+	s, err := getServer(sd)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	if err := s.start(); err != nil {
+		return s.recErr(err, errOut)
+	}
+
+	var opts []tsnet.FunnelOption
+	if funnelOnly != 0 {
+		opts = append(opts, tsnet.FunnelOnly())
+	}
+	ln, err := s.s.ListenFunnel(C.GoString(network), C.GoString(addr), opts...)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	return listen(s, ln, listenerOut, errOut)
+}
+
+// connFlagFunnel marks a connection which arrived via Funnel, as opposed
+// to from inside the tailnet. Keep in sync with TAILSCALE_CONN_FUNNEL.
+const connFlagFunnel = 1 << 0
+
+// connInfo returns the conn* flags and the remote address of an accepted
+// connection. For Funnel connections, the remote address is the public
+// client rather than the ingress node.
+func connInfo(c net.Conn) (flags byte, remoteAddr string) {
+	remoteAddr = c.RemoteAddr().String()
+	// tsnet.Server.ListenFunnel terminates TLS, wrapping the FunnelConn.
+	inner := c
+	if tc, ok := inner.(*tls.Conn); ok {
+		inner = tc.NetConn()
+	}
+	if fc, ok := inner.(*ipn.FunnelConn); ok {
+		flags |= connFlagFunnel
+		remoteAddr = fc.Src.String()
 	}
+	return flags, remoteAddr
+}
 
+// listen hands the connections accepted on ln over to C.
+func listen(s *server, ln net.Listener, listenerOut, errOut *C.int) C.int {
 	// The tailscale_listener we return to C is one side of a socketpair(2).
 	// We do this so we can proactively call ln.Accept in a goroutine and
 	// feed an fd for the connection through the listener. This lets C use
@@ -186,7 +931,8 @@ func TsnetListen(sd C.int, network, addr *C.char, listenerOut *C.int) C.int {
 	// tailscale_accept, which avoids a blocking call on the far side.
 	fds, err := syscall.Socketpair(syscall.AF_LOCAL, syscall.SOCK_STREAM, 0)
 	if err != nil {
-		return s.recErr(err)
+		ln.Close()
+		return s.recErr(err, errOut)
 	}
 	sp := fds[1]
 	fdC := C.int(fds[0])
@@ -236,8 +982,13 @@ func TsnetListen(sd C.int, network, addr *C.char, listenerOut *C.int) C.int {
 				netConn.Close()
 				continue
 			}
+			// A flags byte and the remote address are sent along
+			// with the fd, as the connection itself is a socketpair
+			// on the C side.
+			flags, remoteAddr := connInfo(netConn)
+			msg := append([]byte{flags}, remoteAddr...)
 			rights := syscall.UnixRights(int(connFd))
-			err = syscall.Sendmsg(sp, nil, rights, nil, 0)
+			err = syscall.Sendmsg(sp, msg, rights, nil, 0)
 			if err != nil {
 				// We handle sp being closed in the read goroutine above.
 				if s.s.Logf != nil {
@@ -254,6 +1005,104 @@ func TsnetListen(sd C.int, network, addr *C.char, listenerOut *C.int) C.int {
 	return 0
 }
 
+//export TsnetListenPacket
+func TsnetListenPacket(sd C.int, network, addr *C.char, packetOut, errOut *C.int) C.int {
+	s, err := getServer(sd)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	if err := s.start(); err != nil {
+		return s.recErr(err, errOut)
+	}
+
+	pc, err := s.s.ListenPacket(C.GoString(network), C.GoString(addr))
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+
+	// The tailscale_packet we return to C is one side of a SOCK_SEQPACKET
+	// socketpair(2), so that datagram boundaries are preserved. Each
+	// datagram is prefixed with the address of the peer it came from or
+	// is going to, see packetHeaderLen.
+	fds, err := syscall.Socketpair(syscall.AF_LOCAL, syscall.SOCK_SEQPACKET, 0)
+	if err != nil {
+		pc.Close()
+		return s.recErr(err, errOut)
+	}
+	sp := fds[1]
+	fdC := C.int(fds[0])
+
+	packetConns.mu.Lock()
+	if packetConns.m == nil {
+		packetConns.m = map[C.int]*packetConn{}
+	}
+	packetConns.m[fdC] = &packetConn{s: s, pc: pc, fd: sp}
+	packetConns.mu.Unlock()
+
+	cleanup := func() {
+		// Both goroutines below call cleanup, be careful to only
+		// syscall.Close once as the FD may have been reallocated.
+		packetConns.mu.Lock()
+		if tsPc, ok := packetConns.m[fdC]; ok && tsPc.pc == pc {
+			delete(packetConns.m, fdC)
+			syscall.Close(sp)
+		}
+		packetConns.mu.Unlock()
+
+		pc.Close()
+	}
+	go func() {
+		// Datagrams from the tailnet to C.
+		defer cleanup()
+		buf := make([]byte, packetHeaderLen+maxPacketLen)
+		for {
+			n, from, err := pc.ReadFrom(buf[packetHeaderLen:])
+			if err != nil {
+				return
+			}
+			ap, err := netip.ParseAddrPort(from.String())
+			if err != nil {
+				continue
+			}
+			ip := ap.Addr().As16()
+			copy(buf, ip[:])
+			binary.BigEndian.PutUint16(buf[16:packetHeaderLen], ap.Port())
+			if _, err := syscall.Write(sp, buf[:packetHeaderLen+n]); err != nil {
+				return
+			}
+		}
+	}()
+	go func() {
+		// Datagrams from C to the tailnet. Reading zero bytes means
+		// C closed its side and we can tear down the packet conn.
+		defer cleanup()
+		buf := make([]byte, packetHeaderLen+maxPacketLen)
+		for {
+			n, err := syscall.Read(sp, buf)
+			if err == syscall.EINTR {
+				continue
+			}
+			if err != nil || n == 0 {
+				return
+			}
+			if n < packetHeaderLen {
+				continue
+			}
+			ip := netip.AddrFrom16([16]byte(buf[:16])).Unmap()
+			port := binary.BigEndian.Uint16(buf[16:packetHeaderLen])
+			to := net.UDPAddrFromAddrPort(netip.AddrPortFrom(ip, port))
+			if _, err := pc.WriteTo(buf[packetHeaderLen:n], to); err != nil {
+				if s.s.Logf != nil {
+					s.s.Logf("libtailscale.packet: write to %v: %v", to, err)
+				}
+			}
+		}
+	}()
+
+	*packetOut = fdC
+	return 0
+}
+
 func newConn(s *server, netConn net.Conn, connOut *C.int) error {
 	fds, err := syscall.Socketpair(syscall.AF_LOCAL, syscall.SOCK_STREAM, 0)
 	if err != nil {
@@ -310,66 +1159,173 @@ func newConn(s *server, netConn net.Conn, connOut *C.int) error {
 }
 
 //export TsnetDial
-func TsnetDial(sd C.int, network, addr *C.char, connOut *C.int) C.int {
+func TsnetDial(sd C.int, network, addr *C.char, connOut, errOut *C.int) C.int {
 	s, err := getServer(sd)
 	if err != nil {
-		return s.recErr(err)
+		return s.recErr(err, errOut)
 	}
-	netConn, err := s.s.Dial(context.Background(), C.GoString(network), C.GoString(addr))
+	if err := s.start(); err != nil {
+		return s.recErr(err, errOut)
+	}
+	netConn, err := s.s.Dial(s.ctx, C.GoString(network), C.GoString(addr))
 	if err != nil {
-		return s.recErr(err)
+		return s.recErr(err, errOut)
 	}
-	if newConn(s, netConn, connOut); err != nil {
-		return s.recErr(err)
+	return s.passConn(netConn, connOut, errOut)
+}
+
+// passConn hands netConn over to C as the conn in connOut, or closes it if
+// that fails.
+func (s *server) passConn(netConn net.Conn, connOut, errOut *C.int) C.int {
+	if err := newConn(s, netConn, connOut); err != nil {
+		netConn.Close()
+		return s.recErr(err, errOut)
 	}
 	return 0
 }
 
+// opErrmsgLen bounds the error message in the result of a tailscale_op,
+// including its NUL terminator. Keep in sync with TAILSCALE_OP_ERRMSG_LEN.
+const opErrmsgLen = 4096
+
+// startOp runs op in the background for C, as a tailscale_op.
+//
+// The tailscale_op is one side of a SOCK_SEQPACKET socketpair(2), on which
+// a single message with the result of op is sent once it completes: the
+// native-endian int32 error code, followed by the error message, if any.
+// The connection returned by op is passed along with it, like for listeners.
+// C closing the tailscale_op cancels op if it is still running.
+func startOp(s *server, opOut, errOut *C.int, op func(ctx context.Context) (net.Conn, error)) C.int {
+	fds, err := syscall.Socketpair(syscall.AF_LOCAL, syscall.SOCK_SEQPACKET, 0)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	sp := fds[1]
+	ctx, cancel := context.WithCancel(s.ctx)
+	done := make(chan struct{})
+
+	go func() {
+		// Nothing is written to sp from C, so this blocks until C
+		// closes the op, which cancels op if it is still running.
+		var buf [1]byte
+		syscall.Read(sp, buf[:])
+		cancel()
+		<-done
+		syscall.Close(sp)
+	}()
+	go func() {
+		defer close(done)
+		netConn, err := op(ctx)
+
+		var code C.int
+		var msg []byte
+		var rights []byte
+		if err != nil {
+			code = s.errCode(err)
+			msg = []byte(err.Error())
+			if len(msg) > opErrmsgLen-1 {
+				msg = msg[:opErrmsgLen-1]
+			}
+		} else if netConn != nil {
+			var connFd C.int
+			if err := newConn(s, netConn, &connFd); err != nil {
+				netConn.Close()
+				code = s.errCode(err)
+				msg = []byte(err.Error())
+			} else {
+				rights = syscall.UnixRights(int(connFd))
+				defer syscall.Close(int(connFd)) // now owned by recvmsg
+			}
+		}
+
+		buf := binary.NativeEndian.AppendUint32(nil, uint32(code))
+		buf = append(buf, msg...)
+		if err := syscall.Sendmsg(sp, buf, rights, nil, 0); err != nil {
+			// C closed the op already, and nobody wants the result.
+			if s.s.Logf != nil {
+				s.s.Logf("libtailscale.op: sendmsg failed: %v", err)
+			}
+		}
+	}()
+
+	*opOut = C.int(fds[0])
+	return 0
+}
+
+//export TsnetDialStart
+func TsnetDialStart(sd C.int, network, addr *C.char, opOut, errOut *C.int) C.int {
+	s, err := getServer(sd)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	if err := s.start(); err != nil {
+		return s.recErr(err, errOut)
+	}
+	n, a := C.GoString(network), C.GoString(addr)
+	return startOp(s, opOut, errOut, func(ctx context.Context) (net.Conn, error) {
+		return s.s.Dial(ctx, n, a)
+	})
+}
+
+//export TsnetUpStart
+func TsnetUpStart(sd C.int, opOut, errOut *C.int) C.int {
+	s, err := getServer(sd)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	if err := s.markStarted(); err != nil {
+		return s.recErr(err, errOut)
+	}
+	return startOp(s, opOut, errOut, func(ctx context.Context) (net.Conn, error) {
+		return nil, s.up(ctx)
+	})
+}
+
 //export TsnetSetDir
-func TsnetSetDir(sd C.int, str *C.char) C.int {
+func TsnetSetDir(sd C.int, str *C.char, errOut *C.int) C.int {
 	s, err := getServer(sd)
 	if err != nil {
-		return s.recErr(err)
+		return s.recErr(err, errOut)
 	}
 	s.s.Dir = C.GoString(str)
 	return 0
 }
 
 //export TsnetSetHostname
-func TsnetSetHostname(sd C.int, str *C.char) C.int {
+func TsnetSetHostname(sd C.int, str *C.char, errOut *C.int) C.int {
 	s, err := getServer(sd)
 	if err != nil {
-		return s.recErr(err)
+		return s.recErr(err, errOut)
 	}
 	s.s.Hostname = C.GoString(str)
 	return 0
 }
 
 //export TsnetSetAuthKey
-func TsnetSetAuthKey(sd C.int, str *C.char) C.int {
+func TsnetSetAuthKey(sd C.int, str *C.char, errOut *C.int) C.int {
 	s, err := getServer(sd)
 	if err != nil {
-		return s.recErr(err)
+		return s.recErr(err, errOut)
 	}
 	s.s.AuthKey = C.GoString(str)
 	return 0
 }
 
 //export TsnetSetControlURL
-func TsnetSetControlURL(sd C.int, str *C.char) C.int {
+func TsnetSetControlURL(sd C.int, str *C.char, errOut *C.int) C.int {
 	s, err := getServer(sd)
 	if err != nil {
-		return s.recErr(err)
+		return s.recErr(err, errOut)
 	}
 	s.s.ControlURL = C.GoString(str)
 	return 0
 }
 
 //export TsnetSetEphemeral
-func TsnetSetEphemeral(sd C.int, e int) C.int {
+func TsnetSetEphemeral(sd C.int, e int, errOut *C.int) C.int {
 	s, err := getServer(sd)
 	if err != nil {
-		return s.recErr(err)
+		return s.recErr(err, errOut)
 	}
 	if e == 0 {
 		s.s.Ephemeral = false
@@ -380,10 +1336,10 @@ func TsnetSetEphemeral(sd C.int, e int) C.int {
 }
 
 //export TsnetSetLogFD
-func TsnetSetLogFD(sd, fd C.int) C.int {
+func TsnetSetLogFD(sd, fd C.int, errOut *C.int) C.int {
 	s, err := getServer(sd)
 	if err != nil {
-		return s.recErr(err)
+		return s.recErr(err, errOut)
 	}
 	if fd == -1 {
 		s.s.Logf = logger.Discard
@@ -396,8 +1352,152 @@ func TsnetSetLogFD(sd, fd C.int) C.int {
 	return 0
 }
 
+//export TsnetSetStateStore
+func TsnetSetStateStore(sd C.int, read C.tailscale_state_read_fn, write C.tailscale_state_write_fn, ctx unsafe.Pointer, errOut *C.int) C.int {
+	s, err := getServer(sd)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	if read == nil || write == nil {
+		return s.recErr(errors.New("tailscale_set_state_store: nil callback"), errOut)
+	}
+	if s.started.Load() {
+		return s.recErr(errors.New("tailscale_set_state_store: server already started"), errOut)
+	}
+	s.s.Store = &cStateStore{read: read, write: write, ctx: ctx}
+	return 0
+}
+
+//export TsnetSetStateInMemory
+func TsnetSetStateInMemory(sd C.int, errOut *C.int) C.int {
+	s, err := getServer(sd)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	if s.started.Load() {
+		return s.recErr(errors.New("tailscale_set_state_in_memory: server already started"), errOut)
+	}
+	s.s.Store = new(mem.Store)
+	s.inMemory = true
+	return 0
+}
+
+// stateStore returns the store the server keeps its state in. Before the
+// server is started, that opens the file store tsnet would use under its
+// directory, unless a store was set.
+func (s *server) stateStore() (ipn.StateStore, error) {
+	if s.s.Store != nil {
+		return s.s.Store, nil
+	}
+	if s.started.Load() || s.s.Dir == "" {
+		return nil, errors.New("server has no state directory or store")
+	}
+	st, err := store.New(logger.Discard, filepath.Join(s.s.Dir, "tailscaled.state"))
+	if err != nil {
+		return nil, err
+	}
+	s.s.Store = st
+	return st, nil
+}
+
+//export TsnetGetState
+func TsnetGetState(sd C.int, key *C.char, buf *C.char, buflen C.size_t, lenOut *C.size_t, errOut *C.int) C.int {
+	if lenOut == nil {
+		panic("get_state passed nil len_out")
+	}
+	*lenOut = 0
+
+	s, err := getServer(sd)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	st, err := s.stateStore()
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	id := C.GoString(key)
+	bs, err := st.ReadState(ipn.StateKey(id))
+	if errors.Is(err, ipn.ErrStateNotExist) {
+		s.recErr(fmt.Errorf("libtailscale: no state %q", id), errOut)
+		return C.ENOENT
+	} else if err != nil {
+		return s.recErr(err, errOut)
+	}
+	*lenOut = C.size_t(len(bs))
+	if len(bs) > int(buflen) {
+		s.recErr(fmt.Errorf("libtailscale: state %q of %d bytes is too long for buflen %d", id, len(bs), buflen), errOut)
+		return C.ERANGE
+	}
+	if len(bs) > 0 {
+		copy(unsafe.Slice((*byte)(unsafe.Pointer(buf)), buflen), bs)
+	}
+	return 0
+}
+
+//export TsnetSetState
+func TsnetSetState(sd C.int, key *C.char, value *C.char, valueLen C.size_t, errOut *C.int) C.int {
+	s, err := getServer(sd)
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	st, err := s.stateStore()
+	if err != nil {
+		return s.recErr(err, errOut)
+	}
+	var bs []byte
+	if valueLen > 0 {
+		bs = C.GoBytes(unsafe.Pointer(value), C.int(valueLen))
+	}
+	return s.recErr(st.WriteState(ipn.StateKey(C.GoString(key)), bs), errOut)
+}
+
+// cStateStore is an ipn.StateStore calling back into C, as set by
+// tailscale_set_state_store.
+type cStateStore struct {
+	read  C.tailscale_state_read_fn
+	write C.tailscale_state_write_fn
+	ctx   unsafe.Pointer
+}
+
+// stateReadBufLen is the initial buffer size for reading a state value.
+// Values are mostly keys and small JSON documents.
+const stateReadBufLen = 4096
+
+func (st *cStateStore) ReadState(id ipn.StateKey) ([]byte, error) {
+	key := append([]byte(id), 0)
+	buf := make([]byte, stateReadBufLen)
+	for {
+		var n C.size_t
+		rc := C.call_state_read(st.read, st.ctx, (*C.char)(unsafe.Pointer(&key[0])), (*C.char)(unsafe.Pointer(&buf[0])), C.size_t(len(buf)), &n)
+		switch {
+		case rc == 0 && int(n) <= len(buf):
+			return buf[:n], nil
+		case rc == C.ENOENT:
+			return nil, ipn.ErrStateNotExist
+		case rc == C.ERANGE && int(n) > len(buf):
+			buf = make([]byte, n)
+		case rc == 0 || rc == C.ERANGE:
+			return nil, fmt.Errorf("state store: reading %q: invalid length %d for buffer of %d", id, n, len(buf))
+		default:
+			return nil, fmt.Errorf("state store: reading %q: %w", id, syscall.Errno(rc))
+		}
+	}
+}
+
+func (st *cStateStore) WriteState(id ipn.StateKey, bs []byte) error {
+	key := append([]byte(id), 0)
+	var value *C.char
+	if len(bs) > 0 {
+		value = (*C.char)(unsafe.Pointer(&bs[0]))
+	}
+	if rc := C.call_state_write(st.write, st.ctx, (*C.char)(unsafe.Pointer(&key[0])), value, C.size_t(len(bs))); rc != 0 {
+		return fmt.Errorf("state store: writing %q: %w", id, syscall.Errno(rc))
+	}
+	return nil
+}
+
 //export TsnetLoopback
-func TsnetLoopback(sd C.int, addrOut *C.char, addrLen C.size_t, proxyOut *C.char, localOut *C.char) C.int {
+func TsnetLoopback(sd C.int, addrOut *C.char, addrLen C.size_t, proxyOut *C.char, localOut *C.char, errOut *C.int) C.int {
 	// Panic here to ensure we always leave the out values NUL-terminated.
 	if addrOut == nil {
 		panic("loopback_api passed nil addr_out")
@@ -416,20 +1516,23 @@ func TsnetLoopback(sd C.int, addrOut *C.char, addrLen C.size_t, proxyOut *C.char
 
 	s, err := getServer(sd)
 	if err != nil {
-		return s.recErr(err)
+		return s.recErr(err, errOut)
+	}
+	if err := s.start(); err != nil {
+		return s.recErr(err, errOut)
 	}
 	addr, proxyCred, localAPICred, err := s.s.Loopback()
 	if err != nil {
-		return s.recErr(err)
+		return s.recErr(err, errOut)
 	}
 	if len(proxyCred) != 32 {
-		return s.recErr(fmt.Errorf("libtailscale: len(proxyCred)=%d, want 32", len(proxyCred)))
+		return s.recErr(fmt.Errorf("libtailscale: len(proxyCred)=%d, want 32", len(proxyCred)), errOut)
 	}
 	if len(localAPICred) != 32 {
-		return s.recErr(fmt.Errorf("libtailscale: len(localAPICred)=%d, want 32", len(localAPICred)))
+		return s.recErr(fmt.Errorf("libtailscale: len(localAPICred)=%d, want 32", len(localAPICred)), errOut)
 	}
 	if len(addr)+1 > int(addrLen) {
-		return s.recErr(fmt.Errorf("libtailscale: loopback addr of %d bytes is too long for addrlen %d", len(addr), addrLen))
+		return s.recErr(fmt.Errorf("libtailscale: loopback addr of %d bytes is too long for addrlen %d", len(addr), addrLen), errOut)
 	}
 	out := unsafe.Slice((*byte)(unsafe.Pointer(addrOut)), addrLen)
 	n := copy(out, addr)
@@ -42,19 +42,73 @@ extern int tailscale_start(tailscale sd);
 
 // tailscale_up connects the server to the tailnet and waits for it to be usable.
 //
-// To cancel an in-progress call to tailscale_up, use tailscale_close.
+// To cancel an in-progress call to tailscale_up, use tailscale_close or
+// tailscale_shutdown.
 //
 // Returns zero on success or -1 on error, call tailscale_errmsg for details.
 extern int tailscale_up(tailscale sd);
 
+// tailscale_up_timeout is like tailscale_up, but gives up waiting after
+// timeout_ms milliseconds.
+//
+// Returns:
+// 	0         - success
+// 	ETIMEDOUT - the server was not usable within timeout_ms
+// 	-1        - other error, call tailscale_errmsg for details
+extern int tailscale_up_timeout(tailscale sd, int timeout_ms);
+
+// tailscale_up_status writes the status of the server as of the last
+// successful tailscale_up, tailscale_up_timeout or tailscale_up_start to buf,
+// as a NUL-terminated JSON object in the format of the LocalAPI status.
+//
+// Returns:
+// 	0      - success
+// 	ERANGE - insufficient storage for buf, call again with a bigger one
+// 	-1     - other error, like the server not being up yet, call tailscale_errmsg for details
+extern int tailscale_up_status(tailscale sd, char* buf, size_t buflen);
+
 // tailscale_close shuts down the server.
 //
+// All listeners and connections of the server are closed along with it:
+// tailscale_accept returns ESHUTDOWN and connections read EOF. Their file
+// descriptors must still be closed with close(2).
+//
 // Returns:
 // 	0     - success
 // 	EBADF - sd is not a valid tailscale
 // 	-1    - other error, details printed to the tsnet logger
 extern int tailscale_close(tailscale sd);
 
+// tailscale_shutdown_report describes what tailscale_shutdown did.
+typedef struct tailscale_shutdown_report {
+	// Listeners (including packet listeners) closed.
+	int listeners_closed;
+	// Connections which finished on their own before the timeout.
+	int conns_drained;
+	// Connections still open at the timeout, which were closed.
+	int conns_forced;
+	// Non-zero if the node was ephemeral and logged out of the tailnet.
+	int logged_out;
+} tailscale_shutdown_report;
+
+// tailscale_shutdown gracefully shuts down the server.
+//
+// In order, it:
+// 	- cancels any in-progress tailscale_up or tailscale_dial,
+// 	- closes all listeners, as tailscale_close does,
+// 	- waits up to timeout_ms milliseconds for open connections to finish,
+// 	- closes the connections still open,
+// 	- logs out the node if it is ephemeral, removing it from the tailnet,
+// 	- closes the server, as tailscale_close does.
+//
+// What was done is written to report_out, unless it is NULL.
+//
+// Returns:
+// 	0     - success
+// 	EBADF - sd is not a valid tailscale
+// 	-1    - other error, details printed to the tsnet logger
+extern int tailscale_shutdown(tailscale sd, int timeout_ms, tailscale_shutdown_report* report_out);
+
 // The following set tailscale configuration options.
 //
 // Configure these options before any explicit or implicit call to tailscale_start.
@@ -74,6 +128,15 @@ extern int tailscale_set_ephemeral(tailscale sd, int ephemeral);
 // Returns zero on success or -1 on error, call tailscale_errmsg for details.
 extern int tailscale_set_logfd(tailscale sd, int fd);
 
+// tailscale_getips writes the tailnet IP addresses assigned to the server to buf,
+// as a NUL-terminated, comma-separated list.
+//
+// There is at most one IPv4 and one IPv6 address. The list is empty if the
+// server has not been assigned addresses yet, see tailscale_up.
+//
+// Returns zero on success or -1 on error, call tailscale_errmsg for details.
+extern int tailscale_getips(tailscale sd, char* buf, size_t buflen);
+
 // A tailscale_conn is a connection to an address on the tailnet.
 //
 // It is a pipe(2) on which you can use read(2), write(2), and close(2).
@@ -116,6 +179,58 @@ typedef int tailscale_listener;
 // Returns zero on success or -1 on error, call tailscale_errmsg for details.
 extern int tailscale_listen(tailscale sd, const char* network, const char* addr, tailscale_listener* listener_out);
 
+// tailscale_listen_tls is like tailscale_listen, but every connection accepted
+// on the listener is TLS-terminated with a certificate for the server's name,
+// provisioned automatically from Let's Encrypt.
+//
+// HTTPS must be enabled on the tailnet. See tailscale_cert_domains for the
+// names a certificate can be obtained for.
+//
+// network must be "tcp".
+//
+// Returns zero on success or -1 on error, call tailscale_errmsg for details.
+extern int tailscale_listen_tls(tailscale sd, const char* network, const char* addr, tailscale_listener* listener_out);
+
+// tailscale_listen_funnel is like tailscale_listen_tls, but also accepts
+// connections from the public internet via Tailscale Funnel.
+//
+// Funnel must be allowed for the server by the tailnet policy, and addr must
+// use one of the ports Funnel supports (443, 8443 or 10000).
+//
+// If funnel_only is non-zero, only connections from Funnel are accepted and
+// connections from inside the tailnet are refused. See tailscale_accept_info
+// to tell connections from Funnel apart.
+//
+// Returns zero on success or -1 on error, call tailscale_errmsg for details.
+extern int tailscale_listen_funnel(tailscale sd, const char* network, const char* addr, int funnel_only, tailscale_listener* listener_out);
+
+// tailscale_cert_domains writes the domain names the server can obtain TLS
+// certificates for to buf, as a NUL-terminated, comma-separated list.
+//
+// The list is empty if HTTPS is not enabled on the tailnet, or the server
+// is not up yet.
+//
+// Returns:
+// 	0      - success
+// 	ERANGE - insufficient storage for buf, call again with a bigger one
+// 	-1     - other error, call tailscale_errmsg for details
+extern int tailscale_cert_domains(tailscale sd, char* buf, size_t buflen);
+
+// tailscale_cert_pair obtains a TLS certificate and private key for domain,
+// one of the names from tailscale_cert_domains.
+//
+// The certificate is provisioned from Let's Encrypt on first use, then cached
+// in the state directory and renewed as needed.
+//
+// The PEM-encoded private key followed by the PEM-encoded certificate chain
+// is written to buf, NUL-terminated.
+//
+// Returns:
+// 	0      - success
+// 	ERANGE - insufficient storage for buf, call again with a bigger one
+// 	-1     - other error, call tailscale_errmsg for details
+extern int tailscale_cert_pair(tailscale sd, const char* domain, char* buf, size_t buflen);
+
 // tailscale_accept accepts a connection on a tailscale_listener.
 //
 // It is the spiritual equivalent to accept(2).
@@ -123,11 +238,73 @@ extern int tailscale_listen(tailscale sd, const char* network, const char* addr,
 // The newly allocated connection is written to conn_out.
 //
 // Returns:
-// 	0     - success
-// 	EBADF - listener is not a valid tailscale
-// 	-1    - call tailscale_errmsg for details
+// 	0         - success
+// 	ESHUTDOWN - the listener was closed, for example by closing its server
+// 	-1        - recvmsg(2) failed, see errno for details
 extern int tailscale_accept(tailscale_listener listener, tailscale_conn* conn_out);
 
+// tailscale_accept_addr is like tailscale_accept, but also writes the tailnet
+// address of the remote peer to remote_addr_out, as a NUL-terminated
+// "ip:port" string.
+//
+// The connection itself is a socketpair, so getpeername(2) on it does not
+// tell where it came from.
+//
+// remote_addr_out may be NULL if the address is not needed.
+extern int tailscale_accept_addr(tailscale_listener listener, tailscale_conn* conn_out, char* remote_addr_out, size_t addrlen);
+
+// TAILSCALE_CONN_FUNNEL is set in the flags of a connection which arrived
+// from the public internet via Funnel. Its remote address is then the address
+// of the public client.
+#define TAILSCALE_CONN_FUNNEL 1
+
+// tailscale_accept_info is like tailscale_accept_addr, but also writes the
+// TAILSCALE_CONN_* flags of the connection to flags_out.
+//
+// flags_out may be NULL if the flags are not needed.
+extern int tailscale_accept_info(tailscale_listener listener, tailscale_conn* conn_out, char* remote_addr_out, size_t addrlen, int* flags_out);
+
+// tailscale_whois looks up the node and user owning a tailnet address.
+//
+// addr is a NUL-terminated string of an IP address, or an "ip:port" pair
+// such as the one from tailscale_accept_addr.
+//
+// The result is written to buf as NUL-terminated JSON, in the format of the
+// LocalAPI whois endpoint (see the godoc for apitype.WhoIsResponse).
+//
+// Returns:
+// 	0      - success
+// 	ERANGE - insufficient storage for buf, call again with a bigger one
+// 	-1     - other error, call tailscale_errmsg for details
+extern int tailscale_whois(tailscale sd, const char* addr, char* buf, size_t buflen);
+
+// A tailscale_packet is a datagram socket on the tailnet.
+//
+// Under the hood, a tailscale_packet is one half of a SOCK_SEQPACKET
+// socketpair, so every read(2) returns a single datagram received on the
+// tailnet and every write(2) sends a single datagram. Close it with close(2).
+//
+// Each datagram read or written is prefixed with a header of
+// TAILSCALE_PACKET_HEADER_LEN bytes holding the address of the peer it came
+// from or is sent to: the 16 byte IP address (IPv4-mapped for IPv4) followed
+// by the port in network byte order.
+typedef int tailscale_packet;
+
+#define TAILSCALE_PACKET_HEADER_LEN 18
+
+// tailscale_listen_packet listens for datagrams on the tailnet.
+//
+// The newly allocated datagram socket is written to packet_out.
+//
+// network is a NUL-terminated string of the form "udp", "udp4" or "udp6".
+// addr is a NUL-terminated string of the form "ip:port", where ip is one
+// of the tailnet addresses of the server, see tailscale_getips.
+//
+// It will start the server if it has not been started yet.
+//
+// Returns zero on success or -1 on error, call tailscale_errmsg for details.
+extern int tailscale_listen_packet(tailscale sd, const char* network, const char* addr, tailscale_packet* packet_out);
+
 // tailscale_loopback starts a loopback address server.
 //
 // The server has multiple functions.
@@ -161,6 +338,190 @@ extern int tailscale_loopback(tailscale sd, char* addr_out, size_t addrlen, char
 // 	ERANGE - insufficient storage for buf
 extern int tailscale_errmsg(tailscale sd, char* buf, size_t buflen);
 
+// Error codes returned by tailscale_errcode.
+#define TAILSCALE_ERR_NONE                0 // the last call succeeded
+#define TAILSCALE_ERR_OTHER               1 // an error not classified below
+#define TAILSCALE_ERR_NEEDS_LOGIN         2 // the node must be logged in interactively
+#define TAILSCALE_ERR_AUTHKEY_INVALID     3 // the auth key was rejected or has expired
+#define TAILSCALE_ERR_CONTROL_UNREACHABLE 4 // the control server could not be reached
+#define TAILSCALE_ERR_CONN_REFUSED        5 // the remote peer refused the connection
+#define TAILSCALE_ERR_TIMEOUT             6 // the operation timed out
+#define TAILSCALE_ERR_ADDR_IN_USE         7 // the address is already being listened on
+#define TAILSCALE_ERR_UNKNOWN_HANDLE      8 // sd is not a valid tailscale
+#define TAILSCALE_ERR_SERVER_CLOSED       9 // the server was closed during the call
+
+// tailscale_errcode classifies the last error, as described by
+// tailscale_errmsg, into one of the TAILSCALE_ERR_* codes.
+//
+// When tailscale_up, tailscale_up_timeout or tailscale_up_start fail,
+// including by timing out, the code tells what the server was still waiting
+// for, if known.
+//
+// Returns the error code, or TAILSCALE_ERR_UNKNOWN_HANDLE if sd is not
+// a valid tailscale.
+extern int tailscale_errcode(tailscale sd);
+
+// A tailscale_error describes the failure of a single call.
+//
+// tailscale_errmsg and tailscale_errcode describe the last error of a
+// server, which another thread may have replaced by the time they are
+// called. The _err variants of the functions above avoid that: they
+// behave the same, but whenever they return non-zero, they also write a
+// tailscale_error for that call to err_out, if it is not NULL.
+//
+// Each tailscale_error must be released with tailscale_error_free.
+typedef int tailscale_error;
+
+extern int tailscale_start_err(tailscale sd, tailscale_error* err_out);
+extern int tailscale_up_err(tailscale sd, tailscale_error* err_out);
+extern int tailscale_up_timeout_err(tailscale sd, int timeout_ms, tailscale_error* err_out);
+extern int tailscale_up_status_err(tailscale sd, char* buf, size_t buflen, tailscale_error* err_out);
+extern int tailscale_set_dir_err(tailscale sd, const char* dir, tailscale_error* err_out);
+extern int tailscale_set_hostname_err(tailscale sd, const char* hostname, tailscale_error* err_out);
+extern int tailscale_set_authkey_err(tailscale sd, const char* authkey, tailscale_error* err_out);
+extern int tailscale_set_control_url_err(tailscale sd, const char* control_url, tailscale_error* err_out);
+extern int tailscale_set_ephemeral_err(tailscale sd, int ephemeral, tailscale_error* err_out);
+extern int tailscale_set_logfd_err(tailscale sd, int fd, tailscale_error* err_out);
+extern int tailscale_getips_err(tailscale sd, char* buf, size_t buflen, tailscale_error* err_out);
+extern int tailscale_dial_err(tailscale sd, const char* network, const char* addr, tailscale_conn* conn_out, tailscale_error* err_out);
+extern int tailscale_listen_err(tailscale sd, const char* network, const char* addr, tailscale_listener* listener_out, tailscale_error* err_out);
+extern int tailscale_listen_tls_err(tailscale sd, const char* network, const char* addr, tailscale_listener* listener_out, tailscale_error* err_out);
+extern int tailscale_listen_funnel_err(tailscale sd, const char* network, const char* addr, int funnel_only, tailscale_listener* listener_out, tailscale_error* err_out);
+extern int tailscale_listen_packet_err(tailscale sd, const char* network, const char* addr, tailscale_packet* packet_out, tailscale_error* err_out);
+extern int tailscale_cert_domains_err(tailscale sd, char* buf, size_t buflen, tailscale_error* err_out);
+extern int tailscale_cert_pair_err(tailscale sd, const char* domain, char* buf, size_t buflen, tailscale_error* err_out);
+extern int tailscale_whois_err(tailscale sd, const char* addr, char* buf, size_t buflen, tailscale_error* err_out);
+extern int tailscale_loopback_err(tailscale sd, char* addr_out, size_t addrlen, char* proxy_cred_out, char* local_api_cred_out, tailscale_error* err_out);
+
+// tailscale_error_code returns the TAILSCALE_ERR_* code classifying err,
+// like tailscale_errcode.
+//
+// Returns the error code, or TAILSCALE_ERR_UNKNOWN_HANDLE if err is not
+// a valid tailscale_error.
+extern int tailscale_error_code(tailscale_error err);
+
+// tailscale_error_msg writes the details of err to buf.
+//
+// After returning, buf is always NUL-terminated. The message is not
+// consumed, so on ERANGE call again with a larger buf.
+//
+// Returns:
+// 	0      - success
+// 	EBADF  - err is not a valid tailscale_error
+// 	ERANGE - insufficient storage for buf, the message was truncated
+extern int tailscale_error_msg(tailscale_error err, char* buf, size_t buflen);
+
+// tailscale_error_free releases err.
+extern void tailscale_error_free(tailscale_error err);
+
+// A tailscale_op is an operation running in the background, for use with
+// an event loop instead of blocking a thread.
+//
+// It is one half of a socketpair, which becomes readable once the operation
+// is done. Then collect the result with tailscale_op_result, and close the
+// tailscale_op with close(2). Closing it earlier cancels the operation.
+typedef int tailscale_op;
+
+// TAILSCALE_OP_ERRMSG_LEN is the longest error message of a tailscale_op,
+// including the NUL terminator.
+#define TAILSCALE_OP_ERRMSG_LEN 4096
+
+// tailscale_dial_start is like tailscale_dial, but dials in the background.
+//
+// The new connection is the result of the op, see tailscale_op_result.
+//
+// Returns zero on success or -1 on error, see err_out for details.
+extern int tailscale_dial_start(tailscale sd, const char* network, const char* addr, tailscale_op* op_out, tailscale_error* err_out);
+
+// tailscale_up_start is like tailscale_up, but waits in the background.
+//
+// Returns zero on success or -1 on error, see err_out for details.
+extern int tailscale_up_start(tailscale sd, tailscale_op* op_out, tailscale_error* err_out);
+
+// tailscale_op_result reads the result of op, blocking until it is done
+// unless op was made non-blocking.
+//
+// The connection resulting from the op, if any, is written to conn_out,
+// or -1 if there is none. On failure, the TAILSCALE_ERR_* code is written
+// to code_out and the error message to errmsg, NUL-terminated. An errmsglen
+// of TAILSCALE_OP_ERRMSG_LEN is always enough.
+//
+// Returns:
+// 	0          - success
+// 	-1         - the op failed, see code_out and errmsg
+// 	EAGAIN     - op is non-blocking and not done yet
+// 	ESHUTDOWN  - op hung up without a result
+// 	other      - an errno from recvmsg(2)
+extern int tailscale_op_result(tailscale_op op, tailscale_conn* conn_out, int* code_out, char* errmsg, size_t errmsglen);
+
+// A state store keeps the state of a server, like its machine and node keys,
+// in place of the files under the directory of tailscale_set_dir.
+//
+// The state is a set of values identified by NUL-terminated string keys.
+// The callbacks are called from arbitrary threads, possibly concurrently,
+// with the ctx given to tailscale_set_state_store.
+
+// tailscale_state_read_fn reads the value of key into buf, and writes the
+// length of the value to len_out.
+//
+// Returns:
+// 	0       - success
+// 	ENOENT  - there is no value for key
+// 	ERANGE  - the value is longer than buflen, len_out holds its length
+// 	other   - any other errno, failing the read
+typedef int (*tailscale_state_read_fn)(void* ctx, const char* key, char* buf, size_t buflen, size_t* len_out);
+
+// tailscale_state_write_fn stores the len bytes of value as the value of
+// key, replacing any previous value. value is NULL if len is 0.
+//
+// Returns zero on success, or an errno failing the write.
+typedef int (*tailscale_state_write_fn)(void* ctx, const char* key, const char* value, size_t len);
+
+// tailscale_set_state_store makes the server keep its state with the read and
+// write callbacks, instead of in files. It must be called before the server
+// is started, and ctx must remain valid until tailscale_close returns.
+//
+// Returns zero on success or -1 on error, call tailscale_errmsg for details.
+extern int tailscale_set_state_store(tailscale sd, tailscale_state_read_fn read, tailscale_state_write_fn write, void* ctx);
+extern int tailscale_set_state_store_err(tailscale sd, tailscale_state_read_fn read, tailscale_state_write_fn write, void* ctx, tailscale_error* err_out);
+
+// tailscale_set_state_in_memory makes the server keep its state in memory,
+// so it is lost once the server is closed. That suits ephemeral nodes.
+// It must be called before the server is started.
+//
+// The state is never written to disk, but tsnet can't run without a
+// directory, where it keeps its log configuration and log buffer. Unless
+// tailscale_set_dir was called, a private temporary directory is created
+// when the server starts, and removed by tailscale_close or
+// tailscale_shutdown.
+//
+// Returns zero on success or -1 on error, call tailscale_errmsg for details.
+extern int tailscale_set_state_in_memory(tailscale sd);
+extern int tailscale_set_state_in_memory_err(tailscale sd, tailscale_error* err_out);
+
+// tailscale_get_state reads the value of key from the state of the server
+// into buf, and writes the length of the value to len_out.
+//
+// Before the server is started, this reads from the store set with
+// tailscale_set_state_store or tailscale_set_state_in_memory, or else from the
+// state files under the directory of tailscale_set_dir, which must be set.
+//
+// Returns:
+// 	0       - success
+// 	ENOENT  - there is no value for key
+// 	ERANGE  - the value is longer than buflen, len_out holds its length
+// 	-1      - other error, call tailscale_errmsg for details
+extern int tailscale_get_state(tailscale sd, const char* key, char* buf, size_t buflen, size_t* len_out);
+extern int tailscale_get_state_err(tailscale sd, const char* key, char* buf, size_t buflen, size_t* len_out, tailscale_error* err_out);
+
+// tailscale_set_state stores the len bytes of value as the value of key in
+// the state of the server, replacing any previous value. See
+// tailscale_get_state for which state that is.
+//
+// Returns zero on success or -1 on error, call tailscale_errmsg for details.
+extern int tailscale_set_state(tailscale sd, const char* key, const char* value, size_t len);
+extern int tailscale_set_state_err(tailscale sd, const char* key, const char* value, size_t len, tailscale_error* err_out);
+
 
 #ifdef __cplusplus
 }
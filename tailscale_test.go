@@ -1,10 +1,20 @@
 package main
 
 import (
+	"context"
+	"crypto/tls"
+	"errors"
+	"fmt"
+	"io"
+	"net"
+	"net/netip"
+	"os"
+	"syscall"
 	"testing"
 	"time"
 
 	"github.com/tailscale/libtailscale/tsnetctest"
+	"tailscale.com/ipn"
 )
 
 func TestConn(t *testing.T) {
@@ -21,7 +31,7 @@ func TestConn(t *testing.T) {
 		t.Fatalf("want no remaining tsnet objects, got %d", rem)
 	}
 
-	var remConns, remLns int
+	var remConns, remLns, remPacketConns int
 
 	for i := 0; i < 50; i++ {
 		conns.mu.Lock()
@@ -32,7 +42,11 @@ func TestConn(t *testing.T) {
 		remLns = len(listeners.m)
 		listeners.mu.Unlock()
 
-		if remConns == 0 && remLns == 0 {
+		packetConns.mu.Lock()
+		remPacketConns = len(packetConns.m)
+		packetConns.mu.Unlock()
+
+		if remConns == 0 && remLns == 0 && remPacketConns == 0 {
 			break
 		}
 
@@ -57,4 +71,156 @@ func TestConn(t *testing.T) {
 	if remLns > 0 {
 		t.Errorf("want no remaining tsnet_listener objects, got %d", remLns)
 	}
+
+	if remPacketConns > 0 {
+		t.Errorf("want no remaining tsnet_packet objects, got %d", remPacketConns)
+	}
+}
+
+func TestErrCode(t *testing.T) {
+	ctx, cancel := context.WithCancel(context.Background())
+	s := &server{ctx: ctx, cancel: cancel}
+
+	tests := []struct {
+		err  error
+		want int
+	}{
+		{nil, errCodeNone},
+		{errors.New("something else"), errCodeOther},
+
+		// Typed errors.
+		{fmt.Errorf("get: %w", errUnknownServer), errCodeUnknownHandle},
+		{fmt.Errorf("up: %w", &codedError{errors.New("up"), errCodeControlUnreachable}), errCodeControlUnreachable},
+		{fmt.Errorf("up: %w", context.DeadlineExceeded), errCodeTimeout},
+		{fmt.Errorf("read: %w", os.ErrDeadlineExceeded), errCodeTimeout},
+		{fmt.Errorf("dial: %w", syscall.ETIMEDOUT), errCodeTimeout},
+		{&net.OpError{Op: "dial", Err: timeoutErr{}}, errCodeTimeout},
+		{fmt.Errorf("dial: %w", syscall.ECONNREFUSED), errCodeConnRefused},
+		{fmt.Errorf("listen: %w", syscall.EADDRINUSE), errCodeAddrInUse},
+		{fmt.Errorf("up: %w", context.Canceled), errCodeOther},
+		{fmt.Errorf("accept: %w", net.ErrClosed), errCodeOther},
+
+		// Errors that only come as messages.
+		{errors.New("connect tcp 100.64.0.1:22: connection was refused"), errCodeConnRefused},
+		{errors.New("dial tcp 100.64.0.1:22: connection refused"), errCodeConnRefused},
+		{errors.New("tsnet: listener already open for tcp, :80"), errCodeAddrInUse},
+		{errors.New("tsnet.Up: backend: invalid key: unable to validate API key"), errCodeAuthKeyInvalid},
+		{errors.New("tsnet.Up: backend: auth key expired"), errCodeAuthKeyInvalid},
+
+		// Messages that merely look like a classified error.
+		{errors.New("reading authkey file: no such file or directory"), errCodeOther},
+		{errors.New("invalid key: unable to validate API key"), errCodeOther},
+		{errors.New("tsnet.Up: backend: something else"), errCodeOther},
+		{errors.New("node needs login"), errCodeOther},
+		{errors.New("invalid timeout value"), errCodeOther},
+		{errors.New("connection refused by policy, retrying"), errCodeOther},
+		{errors.New("listener already open"), errCodeOther},
+	}
+	for _, tt := range tests {
+		if got := s.errCode(tt.err); int(got) != tt.want {
+			t.Errorf("errCode(%v) = %d, want %d", tt.err, got, tt.want)
+		}
+	}
+
+	cancel()
+	for _, err := range []error{fmt.Errorf("up: %w", context.Canceled), fmt.Errorf("accept: %w", net.ErrClosed)} {
+		if got := s.errCode(err); got != errCodeServerClosed {
+			t.Errorf("errCode(%v) after close = %d, want %d", err, got, errCodeServerClosed)
+		}
+	}
+}
+
+type timeoutErr struct{}
+
+func (timeoutErr) Error() string { return "i/o timeout" }
+func (timeoutErr) Timeout() bool { return true }
+func (timeoutErr) Temporary() bool { return true }
+
+func TestBackendStateErrCode(t *testing.T) {
+	tests := []struct {
+		state string
+		want  int
+	}{
+		{ipn.NeedsLogin.String(), errCodeNeedsLogin},
+		{ipn.NoState.String(), errCodeControlUnreachable},
+		{ipn.Starting.String(), errCodeNone},
+		{ipn.Running.String(), errCodeNone},
+	}
+	for _, tt := range tests {
+		if got := backendStateErrCode(tt.state); int(got) != tt.want {
+			t.Errorf("backendStateErrCode(%q) = %d, want %d", tt.state, got, tt.want)
+		}
+	}
+}
+
+func TestPassConnFails(t *testing.T) {
+	ctx, cancel := context.WithCancel(context.Background())
+	defer cancel()
+	s := &server{ctx: ctx, cancel: cancel}
+	c1, c2 := net.Pipe()
+	defer c2.Close()
+
+	// Leave no file descriptors for the socketpair of newConn.
+	var lim syscall.Rlimit
+	if err := syscall.Getrlimit(syscall.RLIMIT_NOFILE, &lim); err != nil {
+		t.Fatal(err)
+	}
+	none := lim
+	none.Cur = 0
+	if err := syscall.Setrlimit(syscall.RLIMIT_NOFILE, &none); err != nil {
+		t.Fatal(err)
+	}
+	got := s.passConn(c1, nil, nil)
+	if err := syscall.Setrlimit(syscall.RLIMIT_NOFILE, &lim); err != nil {
+		t.Fatal(err)
+	}
+
+	if got != -1 {
+		t.Errorf("passConn = %d, want -1", got)
+	}
+	if _, err := c1.Write([]byte{0}); !errors.Is(err, io.ErrClosedPipe) {
+		t.Errorf("write after passConn failed = %v, want the conn closed", err)
+	}
+	s.mu.Lock()
+	defer s.mu.Unlock()
+	if s.lastErr == "" {
+		t.Errorf("passConn failed without recording an error")
+	}
+}
+
+func TestCopyMsg(t *testing.T) {
+	out := make([]byte, 5)
+	if got := copyMsg(out, "abcd"); got != 0 || string(out) != "abcd\x00" {
+		t.Errorf("copyMsg fitting = %d, %q", got, out)
+	}
+	if got := copyMsg(out, "abcde"); got == 0 || string(out) != "abcd\x00" {
+		t.Errorf("copyMsg too long = %d, %q, want ERANGE and truncated", got, out)
+	}
+}
+
+func TestConnInfo(t *testing.T) {
+	c1, c2 := net.Pipe()
+	defer c1.Close()
+	defer c2.Close()
+	src := netip.MustParseAddrPort("203.0.113.7:51234")
+	fc := &ipn.FunnelConn{Conn: c1, Src: src}
+
+	tests := []struct {
+		name      string
+		conn      net.Conn
+		wantFlags byte
+		wantAddr  string
+	}{
+		{"tailnet", c2, 0, c2.RemoteAddr().String()},
+		{"tailnet tls", tls.Server(c2, &tls.Config{}), 0, c2.RemoteAddr().String()},
+		{"funnel", fc, connFlagFunnel, src.String()},
+		// As accepted from tsnet.Server.ListenFunnel.
+		{"funnel tls", tls.Server(fc, &tls.Config{}), connFlagFunnel, src.String()},
+	}
+	for _, tt := range tests {
+		flags, addr := connInfo(tt.conn)
+		if flags != tt.wantFlags || addr != tt.wantAddr {
+			t.Errorf("%s: connInfo = %d, %q, want %d, %q", tt.name, flags, addr, tt.wantFlags, tt.wantAddr)
+		}
+	}
 }